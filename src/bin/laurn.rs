@@ -1,12 +1,49 @@
 use std::env::current_dir;
+use std::error::Error as StdError;
+use std::fmt;
 use std::io::Error as IoError;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use clap::{App, Arg, SubCommand};
+use clap::{App, Arg, ArgMatches, SubCommand};
 
-use laurn::config::{load_config, Config, ConfigError};
+use laurn::config::{
+    load_project, mode_name, parse_mode, Config, ConfigError, Project, ProjectMode, CONFIG_FILE,
+    MODES,
+};
+use laurn::doctor;
+use laurn::init::{self, InitError};
+use laurn::overlay::{self, Ephemeral};
+use laurn::render;
+use laurn::trust::{self, TrustError, TrustStatus};
+use laurn::user::{load_user_config, Untrusted, UserConfig};
+use laurn::volumes::{self, VolumeError};
 use laurn::{run, BuildError, Container};
 
+// Exit codes of laurn itself. `run` and `shell` otherwise exit with the code of the command, 128 +
+// the signal which killed it, or `run::EXIT_OOM_KILLED` when it failed after the kernel killed a
+// process of the session for lack of memory.
+//
+// The sandbox is set up in processes forked by `run::run`, which return their errors up to `main`
+// as well: the code of the error is their exit code, which the parent returns as the code of the
+// command. These codes are therefore kept above the ones commands commonly use.
+
+/// Invalid command line
+const EXIT_USAGE: i32 = 240;
+/// Invalid configuration
+const EXIT_CONFIG: i32 = 241;
+/// The configuration of the project was not allowed
+const EXIT_UNTRUSTED: i32 = 242;
+/// The environment could not be built by nix
+const EXIT_BUILD: i32 = 243;
+/// The sandbox could not be set up
+const EXIT_SANDBOX: i32 = 244;
+/// The limits of the session could not be applied
+const EXIT_LIMITS: i32 = 245;
+/// Any other failure of laurn (reading the current directory, the trust database, volumes, ...)
+const EXIT_FAILURE: i32 = 246;
+/// `laurn doctor` found that the host can not run a sandbox
+const EXIT_HOST: i32 = 247;
+
 #[derive(Debug)]
 enum Error {
     Run(run::RunError),
@@ -14,6 +51,204 @@ enum Error {
     CurrentDir(IoError),
     CurrentExe(IoError),
     Config(ConfigError),
+    Diff(IoError),
+    Volume(VolumeError),
+    Trust(TrustError),
+    Init(InitError),
+    Render(toml::ser::Error),
+    /// The configuration of the project was not allowed
+    Untrusted(PathBuf),
+    InvalidArgument(&'static str),
+    /// No subcommand was given, see `laurn help`
+    MissingCommand,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Run(ref e) => write!(f, "{}", e),
+            Error::Build(ref e) => write!(f, "{}", e),
+            Error::CurrentDir(ref e) => write!(f, "unable to get the current directory: {}", e),
+            Error::CurrentExe(ref e) => write!(f, "unable to find the laurn binary: {}", e),
+            Error::Config(ref e) => write!(f, "{}", e),
+            Error::Diff(ref e) => write!(f, "unable to read the changes: {}", e),
+            Error::Volume(ref e) => write!(f, "{}", e),
+            Error::Trust(ref e) => write!(f, "{}", e),
+            Error::Init(ref e) => write!(f, "{}", e),
+            Error::Render(ref e) => write!(f, "unable to render the configuration: {}", e),
+            Error::Untrusted(ref root) => write!(
+                f,
+                "the configuration of {} is not allowed, see `laurn allow`",
+                root.display()
+            ),
+            Error::InvalidArgument(arg) => write!(f, "invalid value for --{}", arg),
+            Error::MissingCommand => write!(f, "no command given, see `laurn help`"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Run(ref e) => Some(e),
+            Error::Build(ref e) => Some(e),
+            Error::CurrentDir(ref e) | Error::CurrentExe(ref e) | Error::Diff(ref e) => Some(e),
+            Error::Config(ref e) => Some(e),
+            Error::Volume(ref e) => Some(e),
+            Error::Trust(ref e) => Some(e),
+            Error::Init(ref e) => Some(e),
+            Error::Render(ref e) => Some(e),
+            Error::Untrusted(_) | Error::InvalidArgument(_) | Error::MissingCommand => None,
+        }
+    }
+}
+
+impl Error {
+    fn exit_code(&self) -> i32 {
+        match *self {
+            Error::InvalidArgument(_) | Error::MissingCommand => EXIT_USAGE,
+            Error::Config(_)
+            | Error::Run(run::RunError::Strategy(_))
+            | Error::Run(run::RunError::TmpSize(_))
+            | Error::Run(run::RunError::Volume(VolumeError::InvalidName(_)))
+            | Error::Run(run::RunError::Volume(VolumeError::InvalidTarget(..)))
+            | Error::Run(run::RunError::Volume(VolumeError::Protected(..))) => EXIT_CONFIG,
+            Error::Untrusted(_) => EXIT_UNTRUSTED,
+            Error::Build(_) | Error::Run(run::RunError::Dependencies(_)) => EXIT_BUILD,
+            Error::Run(run::RunError::Cgroup(_)) => EXIT_LIMITS,
+            Error::Run(_) => EXIT_SANDBOX,
+            Error::CurrentDir(_)
+            | Error::CurrentExe(_)
+            | Error::Diff(_)
+            | Error::Volume(_)
+            | Error::Trust(_)
+            | Error::Init(_)
+            | Error::Render(_) => EXIT_FAILURE,
+        }
+    }
+}
+
+fn ephemeral_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("ephemeral")
+            .long("--ephemeral")
+            .takes_value(false)
+            .help("discard the changes made to the project at the end of the session"),
+        Arg::with_name("keep-changes")
+            .long("--keep-changes")
+            .value_name("DIR")
+            .takes_value(true)
+            .help("save the changes of an ephemeral session in DIR instead of discarding them"),
+    ]
+}
+
+/// `--keep-changes` implies `--ephemeral`
+fn ephemeral(matches: &ArgMatches) -> Option<Ephemeral> {
+    if matches.is_present("ephemeral") || matches.is_present("keep-changes") {
+        Some(Ephemeral {
+            keep_changes: matches.value_of("keep-changes").map(PathBuf::from),
+        })
+    } else {
+        None
+    }
+}
+
+fn limits_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("memory")
+            .long("--memory")
+            .value_name("BYTES")
+            .takes_value(true)
+            .help("memory limit of the session (cgroup memory.max)"),
+        Arg::with_name("pids")
+            .long("--pids")
+            .value_name("COUNT")
+            .takes_value(true)
+            .help("maximum number of processes in the session (cgroup pids.max)"),
+        Arg::with_name("cpu")
+            .long("--cpu")
+            .value_name("QUOTA PERIOD")
+            .takes_value(true)
+            .help("cpu bandwidth of the session (cgroup cpu.max)"),
+    ]
+}
+
+/// Command line overrides the configuration
+fn override_config(config: &mut Config, matches: &ArgMatches) -> Result<(), Error> {
+    if matches.is_present("read-only") {
+        config.laurn.project = ProjectMode::ReadOnly;
+    }
+
+    if let Some(memory) = matches.value_of("memory") {
+        config.limits.memory = Some(memory.to_string());
+    }
+    if let Some(pids) = matches.value_of("pids") {
+        let pids = pids.parse().map_err(|_| Error::InvalidArgument("pids"))?;
+        config.limits.pids = Some(pids);
+    }
+    if let Some(cpu) = matches.value_of("cpu") {
+        config.limits.cpu = Some(cpu.to_string());
+    }
+    Ok(())
+}
+
+/// The project the current directory is part of
+fn project() -> Result<Project, Error> {
+    let current_dir = current_dir().map_err(Error::CurrentDir)?;
+    let user_config = load_user_config().map_err(Error::Config)?;
+    load_project(current_dir.as_path(), &user_config).map_err(Error::Config)
+}
+
+fn report_enforced(enforced: &[String]) {
+    for change in enforced {
+        eprintln!("laurn: user policy: {}", change);
+    }
+}
+
+/// Only honour the configuration of the project if the user allowed it
+fn trusted(project: &mut Project, user_config: &UserConfig) -> Result<(), Error> {
+    let (added, removed) = match trust::check(project).map_err(Error::Trust)? {
+        TrustStatus::Trusted => return Ok(()),
+        TrustStatus::New { requested } => {
+            eprintln!(
+                "laurn: the configuration of {} was never allowed",
+                project.root.display()
+            );
+            (requested, Vec::new())
+        }
+        TrustStatus::Changed { added, removed } => {
+            eprintln!(
+                "laurn: the configuration of {} changed since it was allowed",
+                project.root.display()
+            );
+            (added, removed)
+        }
+    };
+    for permission in added.iter() {
+        eprintln!("  + {}", permission);
+    }
+    for permission in removed.iter() {
+        eprintln!("  - {}", permission);
+    }
+    eprintln!("laurn: run `laurn allow` to trust it");
+
+    match user_config.policy.untrusted {
+        Untrusted::Refuse => Err(Error::Untrusted(project.root.clone())),
+        Untrusted::Restrict => {
+            eprintln!("laurn: starting with the most restrictive configuration");
+            // Neither the workspace nor the inherited configurations were allowed: only the
+            // directory of the closest .laurnrc is mounted. Its laurn.nix is not evaluated
+            // either, nix would run whatever it asks for before the sandbox is even set up.
+            let root = project.config_dirs[0].clone();
+            project.root = root.clone();
+            project.nix_expr = trust::restricted_environment().map_err(Error::Trust)?;
+            project.config_dirs = vec![root.clone()];
+            project.amend("untrusted project", |config| *config = Config::restricted());
+            project.enforced =
+                project.amend("user policy", |config| user_config.enforce(&root, config));
+            Ok(())
+        }
+    }
 }
 
 // A dummy hook in bash
@@ -43,7 +278,14 @@ fi
     ))
 }
 
-fn main() -> Result<(), Error> {
+fn main() {
+    if let Err(e) = laurn() {
+        eprintln!("laurn: {}", e);
+        std::process::exit(e.exit_code())
+    }
+}
+
+fn laurn() -> Result<(), Error> {
     let root_arg = Arg::with_name("root")
         .long("--root")
         .takes_value(false)
@@ -51,6 +293,20 @@ fn main() -> Result<(), Error> {
         .required(false)
         .help("should command be run as root");
 
+    let read_only_arg = Arg::with_name("read-only")
+        .long("--read-only")
+        .takes_value(false)
+        .multiple(false)
+        .required(false)
+        .help("mount the project read-only (except for the writable directories)");
+
+    let mode_names: Vec<&str> = MODES.iter().map(|m| mode_name(*m)).collect();
+
+    let json_arg = Arg::with_name("json")
+        .long("--json")
+        .takes_value(false)
+        .help("print JSON");
+
     let matches = App::new("laurn")
         .version("0.0.1")
         .author("Arthur Gautier <laurn@superbaloo.net>")
@@ -59,6 +315,9 @@ fn main() -> Result<(), Error> {
             SubCommand::with_name("run")
                 .about("run a container")
                 .arg(root_arg.clone())
+                .arg(read_only_arg.clone())
+                .args(&ephemeral_args())
+                .args(&limits_args())
                 .arg(
                     Arg::with_name("path")
                         .short("p")
@@ -78,8 +337,108 @@ fn main() -> Result<(), Error> {
         )
         .subcommand(
             SubCommand::with_name("shell")
-                .about("start a shell in the current directory")
-                .arg(root_arg.clone()),
+                .about("start a shell in the current directory, sandboxing the whole project")
+                .arg(root_arg.clone())
+                .arg(read_only_arg.clone())
+                .args(&ephemeral_args())
+                .args(&limits_args())
+                .arg(
+                    Arg::with_name("dry-run")
+                        .long("--dry-run")
+                        .takes_value(false)
+                        .help("print the mounts of the sandbox instead of starting it"),
+                )
+                .arg(json_arg.clone().requires("dry-run")),
+        )
+        .subcommand(
+            SubCommand::with_name("inspect")
+                .about("print the mounts `laurn shell` would set up, without starting it")
+                .arg(read_only_arg.clone())
+                .args(&ephemeral_args())
+                .args(&limits_args())
+                .arg(json_arg.clone()),
+        )
+        .subcommand(
+            SubCommand::with_name("config")
+                .about("inspect the configuration of the project")
+                .subcommand(
+                    SubCommand::with_name("check")
+                        .about("validate the configuration and print the effective one"),
+                )
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about(
+                            "print the configuration of the sandbox, with the origin of each value",
+                        )
+                        .arg(read_only_arg.clone())
+                        .args(&limits_args())
+                        .arg(json_arg.clone().help("print JSON instead of TOML")),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("allow")
+                .about("trust the configuration of the project (.laurnrc and laurn.nix) as it is"),
+        )
+        .subcommand(
+            SubCommand::with_name("deny").about("stop trusting the configuration of the project"),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("summarize the changes kept from an ephemeral session")
+                .arg(
+                    Arg::with_name("dir")
+                        .value_name("DIR")
+                        .takes_value(true)
+                        .required(true)
+                        .help("directory given to --keep-changes"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("volumes")
+                .about("manage the persistent volumes of the project")
+                .subcommand(SubCommand::with_name("ls").about("list the volumes"))
+                .subcommand(
+                    SubCommand::with_name("rm").about("remove a volume").arg(
+                        Arg::with_name("name")
+                            .value_name("NAME")
+                            .takes_value(true)
+                            .required(true)
+                            .help("name of the volume"),
+                    ),
+                )
+                .subcommand(
+                    SubCommand::with_name("prune")
+                        .about("remove the volumes the project does not declare anymore")
+                        .arg(
+                            Arg::with_name("missing")
+                                .long("--missing")
+                                .takes_value(false)
+                                .help("also remove the volumes of projects which can not be found"),
+                        ),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("init")
+                .about("write a laurn.nix and a .laurnrc for the project in the current directory")
+                .arg(
+                    Arg::with_name("mode")
+                        .long("--mode")
+                        .value_name("MODE")
+                        .takes_value(true)
+                        .multiple(true)
+                        .use_delimiter(true)
+                        .possible_values(&mode_names)
+                        .help("type of the project, guessed from its files by default"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("--force")
+                        .takes_value(false)
+                        .help("overwrite the existing files"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("doctor").about("check that the host provides what laurn needs"),
         )
         .subcommand(
             SubCommand::with_name("hook")
@@ -91,37 +450,290 @@ fn main() -> Result<(), Error> {
     if let Some(matches) = matches.subcommand_matches("run") {
         let source = matches.value_of("path").unwrap();
         let source = Path::new(source);
-        let laurn_config = Config::default();
+        let user_config = load_user_config().map_err(Error::Config)?;
+        let mut laurn_config = Config::default();
+        let project_dir = source.parent().unwrap_or_else(|| Path::new("."));
+        report_enforced(&user_config.enforce(project_dir, &mut laurn_config));
+        override_config(&mut laurn_config, matches)?;
 
         let mut command = matches.values_of("command");
         let run_as_root = matches.is_present("root");
 
         let container = Container::build(source).map_err(Error::Build)?;
 
-        let code =
-            run::run(container, laurn_config, command.as_mut(), run_as_root).map_err(Error::Run)?;
+        let code = run::run(
+            container,
+            laurn_config,
+            command.as_mut(),
+            run_as_root,
+            ephemeral(matches),
+            None,
+        )
+        .map_err(Error::Run)?;
         std::process::exit(code)
-    } else if let Some(matches) = matches.subcommand_matches("shell") {
-        let project_dir = current_dir().map_err(Error::CurrentDir)?;
-        let laurn_config_file = project_dir.join(".laurnrc");
-
-        let laurn_config = load_config(laurn_config_file.as_path()).map_err(Error::Config)?;
+    } else if let Some((matches, dry_run)) = matches
+        .subcommand_matches("shell")
+        .map(|m| (m, m.is_present("dry-run")))
+        .or_else(|| matches.subcommand_matches("inspect").map(|m| (m, true)))
+    {
+        let current_dir = current_dir().map_err(Error::CurrentDir)?;
+        let user_config = load_user_config().map_err(Error::Config)?;
+        let mut project =
+            load_project(current_dir.as_path(), &user_config).map_err(Error::Config)?;
+        trusted(&mut project, &user_config)?;
+        report_enforced(&project.enforced);
+        project.amend("command line", |config| override_config(config, matches))?;
+        let Project {
+            root,
+            nix_expr,
+            config_dirs,
+            config: laurn_config,
+            ..
+        } = project;
         let run_as_root = matches.is_present("root");
+        let shell = laurn_config.session.shell.clone();
+        let mut command = shell.as_deref().map(std::iter::once);
 
-        let source = project_dir.join("laurn.nix");
-        let container = Container::build(source.as_path()).map_err(Error::Build)?;
+        let container = Container::build(nix_expr.as_path()).map_err(Error::Build)?;
+        let workspace = run::Workspace {
+            root,
+            workdir: current_dir,
+            config_dirs,
+        };
 
-        //let code = run::run::<clap::Values>(container, laurn_config, None).map_err(Error::Run)?;
-        let code = run::run::<std::iter::Empty<&str>>(container, laurn_config, None, run_as_root)
+        if dry_run {
+            let plan = run::inspect(
+                &container,
+                &laurn_config,
+                ephemeral(matches).as_ref(),
+                Some(&workspace),
+            )
             .map_err(Error::Run)?;
+            if matches.is_present("json") {
+                let value = toml::Value::try_from(&plan).map_err(Error::Render)?;
+                println!("{}", render::json(&value));
+            } else {
+                print!("{}", plan);
+            }
+            std::process::exit(0)
+        }
+
+        let code = run::run(
+            container,
+            laurn_config,
+            command.as_mut(),
+            run_as_root,
+            ephemeral(matches),
+            Some(&workspace),
+        )
+        .map_err(Error::Run)?;
         std::process::exit(code)
+    } else if let Some(matches) = matches.subcommand_matches("config") {
+        if matches.subcommand_matches("check").is_some() {
+            let project = project()?;
+
+            for dir in project.config_dirs.iter() {
+                println!("# {}: ok", dir.join(CONFIG_FILE).display());
+            }
+            println!("# environment: {}", project.nix_expr.display());
+            report_enforced(&project.enforced);
+            for permission in trust::permissions(&project.config) {
+                println!("{}", permission);
+            }
+            std::process::exit(0)
+        } else if let Some(matches) = matches.subcommand_matches("show") {
+            let mut project = project()?;
+            project.amend("command line", |config| override_config(config, matches))?;
+
+            if matches.is_present("json") {
+                let json = render::annotated_json(&project.config, &project.origins)
+                    .map_err(Error::Render)?;
+                println!("{}", json);
+            } else {
+                let toml = render::annotated_toml(&project.config, &project.origins)
+                    .map_err(Error::Render)?;
+                println!("# project: {}", project.root.display());
+                println!("# environment: {}", project.nix_expr.display());
+                if trust::check(&project).map_err(Error::Trust)? != TrustStatus::Trusted {
+                    println!("# not allowed yet, see `laurn allow`");
+                }
+                print!("{}", toml);
+            }
+            std::process::exit(0)
+        }
+    } else if matches.subcommand_matches("allow").is_some() {
+        let project = project()?;
+        trust::allow(&project).map_err(Error::Trust)?;
+        std::process::exit(0)
+    } else if matches.subcommand_matches("deny").is_some() {
+        let project = project()?;
+        trust::deny(&project.root).map_err(Error::Trust)?;
+        std::process::exit(0)
+    } else if let Some(matches) = matches.subcommand_matches("diff") {
+        let project_dir = project()?.root;
+        let upper = Path::new(matches.value_of("dir").unwrap()).join("upper");
+
+        for change in overlay::diff(upper.as_path(), project_dir.as_path()).map_err(Error::Diff)? {
+            println!("{}", change);
+        }
+        std::process::exit(0)
+    } else if let Some(matches) = matches.subcommand_matches("volumes") {
+        let project = project()?;
+        let project_dir = project.root;
+
+        if matches.subcommand_matches("ls").is_some() {
+            for (name, path) in volumes::list(project_dir.as_path()).map_err(Error::Volume)? {
+                println!("{}\t{}", name, path.display());
+            }
+            std::process::exit(0)
+        } else if let Some(matches) = matches.subcommand_matches("rm") {
+            let name = matches.value_of("name").unwrap();
+            volumes::remove(project_dir.as_path(), name).map_err(Error::Volume)?;
+            std::process::exit(0)
+        } else if let Some(matches) = matches.subcommand_matches("prune") {
+            let pruned = volumes::prune(
+                project_dir.as_path(),
+                &project.config.laurn.volumes,
+                matches.is_present("missing"),
+            )
+            .map_err(Error::Volume)?;
+            for path in pruned.removed.iter() {
+                println!("removed {}", path.display());
+            }
+            for (project, path) in pruned.missing.iter() {
+                println!(
+                    "kept {}, {} can not be found (--missing to remove it)",
+                    path.display(),
+                    project.display()
+                );
+            }
+            std::process::exit(0)
+        }
+    } else if let Some(matches) = matches.subcommand_matches("init") {
+        let current_dir = current_dir().map_err(Error::CurrentDir)?;
+        let modes = match matches.values_of("mode") {
+            Some(names) => names.filter_map(parse_mode).collect(),
+            None => init::detect(current_dir.as_path()),
+        };
+        let paths = init::init(current_dir.as_path(), &modes, matches.is_present("force"))
+            .map_err(Error::Init)?;
+        for path in paths.iter() {
+            println!("wrote {}", path.display());
+        }
+        eprintln!("laurn: review them, then run `laurn allow` to trust the configuration");
+        std::process::exit(0)
+    } else if matches.subcommand_matches("doctor").is_some() {
+        let checks = doctor::checks();
+        for check in checks.iter() {
+            println!("{}", check);
+        }
+        let failed = checks.iter().any(|c| c.status == doctor::Status::Error);
+        std::process::exit(if failed { EXIT_HOST } else { 0 })
     } else if let Some(matches) = matches.subcommand_matches("hook") {
         if matches.subcommand_matches("bash").is_some() {
-            if let Ok(hook) = hook_bash() {
-                println!("{}", hook);
-                std::process::exit(0);
-            }
+            println!("{}", hook_bash()?);
+            std::process::exit(0);
+        }
+    }
+    Err(Error::MissingCommand)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+
+    use laurn::{CgroupError, StrategyError};
+
+    fn io() -> IoError {
+        IoError::from(ErrorKind::PermissionDenied)
+    }
+
+    #[test]
+    fn exit_codes() {
+        let cases = vec![
+            (Error::InvalidArgument("memory"), EXIT_USAGE),
+            (Error::MissingCommand, EXIT_USAGE),
+            (
+                Error::Config(ConfigError::NotFound(PathBuf::from("/p"))),
+                EXIT_CONFIG,
+            ),
+            (
+                Error::Run(run::RunError::Strategy(StrategyError::Protected(
+                    PathBuf::from(".git"),
+                ))),
+                EXIT_CONFIG,
+            ),
+            (
+                Error::Run(run::RunError::TmpSize("1m,uid=0".to_string())),
+                EXIT_CONFIG,
+            ),
+            (
+                Error::Run(run::RunError::Volume(VolumeError::InvalidName(
+                    ".cache".to_string(),
+                ))),
+                EXIT_CONFIG,
+            ),
+            (
+                Error::Run(run::RunError::Volume(VolumeError::InvalidTarget(
+                    "cache".to_string(),
+                    "/etc".to_string(),
+                ))),
+                EXIT_CONFIG,
+            ),
+            (
+                Error::Run(run::RunError::Volume(VolumeError::Protected(
+                    "cache".to_string(),
+                    PathBuf::from(".git"),
+                ))),
+                EXIT_CONFIG,
+            ),
+            (Error::Untrusted(PathBuf::from("/p")), EXIT_UNTRUSTED),
+            (Error::Build(BuildError::Source(io())), EXIT_BUILD),
+            (
+                Error::Run(run::RunError::Cgroup(CgroupError::NoDelegation)),
+                EXIT_LIMITS,
+            ),
+            (Error::Run(run::RunError::Hide(io())), EXIT_SANDBOX),
+            (
+                Error::Run(run::RunError::Volume(VolumeError::GuessDataDir)),
+                EXIT_SANDBOX,
+            ),
+            (Error::CurrentDir(io()), EXIT_FAILURE),
+            (
+                Error::Volume(VolumeError::NotFound("cache".to_string())),
+                EXIT_FAILURE,
+            ),
+            (Error::Trust(TrustError::GuessDataDir), EXIT_FAILURE),
+        ];
+        for (error, code) in cases {
+            assert_eq!(error.exit_code(), code, "{:?}", error);
         }
     }
-    std::process::exit(1)
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            Error::Untrusted(PathBuf::from("/home/me/project")).to_string(),
+            "the configuration of /home/me/project is not allowed, see `laurn allow`"
+        );
+        assert_eq!(
+            Error::InvalidArgument("memory").to_string(),
+            "invalid value for --memory"
+        );
+        let error = Error::CurrentDir(io());
+        assert_eq!(
+            error.to_string(),
+            "unable to get the current directory: permission denied"
+        );
+        assert!(error.source().is_some());
+
+        // The path of the file is part of the message
+        let parsing = toml::from_str::<Config>("[laurn]\nnetwork = 1\n").unwrap_err();
+        let error = Error::Config(ConfigError::Parsing(PathBuf::from("/p/.laurnrc"), parsing));
+        assert!(error
+            .to_string()
+            .starts_with("/p/.laurnrc: invalid configuration: "));
+        assert!(error.source().and_then(StdError::source).is_some());
+    }
 }
@@ -8,7 +8,9 @@
 //
 //
 
+use std::error::Error;
 use std::ffi::OsStr;
+use std::fmt;
 use std::io::{Error as IoError, Write};
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
@@ -35,7 +37,9 @@ in pkgs.stdenv.mkDerivation rec {{
   src = pkgs.writeScriptBin "start" ''
 #!/bin/bash
 
-export PATH=@binpath@
+# Project-local binaries (node_modules/.bin, ...) are provided by laurn
+export PATH=''${{LAURN_PATH:+$LAURN_PATH:}}@binpath@
+unset LAURN_PATH
 
 if [ $# -gt 0 ]; then
     exec @bashShell@/bin/bash -c "$*"
@@ -75,6 +79,37 @@ pub enum InstantiationFailed {
     StdinNotCaptured,
 }
 
+impl fmt::Display for InstantiationFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InstantiationFailed::Truncated => {
+                write!(f, "the output of nix-instantiate is truncated")
+            }
+            InstantiationFailed::Code { exit_status } => {
+                write!(f, "nix-instantiate exited with {}", exit_status)
+            }
+            InstantiationFailed::Exec(ref e) => write!(f, "unable to run nix-instantiate: {}", e),
+            InstantiationFailed::Write(ref e) => {
+                write!(f, "unable to write to nix-instantiate: {}", e)
+            }
+            InstantiationFailed::StdinNotCaptured => {
+                write!(f, "unable to write to nix-instantiate: stdin not captured")
+            }
+        }
+    }
+}
+
+impl Error for InstantiationFailed {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            InstantiationFailed::Exec(ref e) | InstantiationFailed::Write(ref e) => Some(e),
+            InstantiationFailed::Truncated
+            | InstantiationFailed::Code { .. }
+            | InstantiationFailed::StdinNotCaptured => None,
+        }
+    }
+}
+
 pub struct Instantiate(PathBuf);
 
 impl Instantiate {
@@ -87,7 +122,8 @@ impl Instantiate {
             .spawn()
             .map_err(InstantiationFailed::Exec)?;
 
-        let stdin = (&mut child.stdin)
+        let stdin = child
+            .stdin
             .as_mut()
             .ok_or(InstantiationFailed::StdinNotCaptured)?;
 
@@ -122,6 +158,25 @@ pub enum BuildFailed {
     Truncated,
 }
 
+impl fmt::Display for BuildFailed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildFailed::Code { exit_status } => write!(f, "nix-store exited with {}", exit_status),
+            BuildFailed::Exec(ref e) => write!(f, "unable to run nix-store: {}", e),
+            BuildFailed::Truncated => write!(f, "the output of nix-store is truncated"),
+        }
+    }
+}
+
+impl Error for BuildFailed {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            BuildFailed::Exec(ref e) => Some(e),
+            BuildFailed::Code { .. } | BuildFailed::Truncated => None,
+        }
+    }
+}
+
 pub struct Build {
     pub(crate) output: PathBuf,
 }
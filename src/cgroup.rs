@@ -0,0 +1,305 @@
+/// cgroup v2 resource limits for a sandbox session
+///
+/// systemd delegates the `user@$UID.service` subtree to the user, laurn creates its sessions in
+/// there:
+///   /sys/fs/cgroup/user.slice/user-1000.slice/user@1000.service/laurn/session-$PID
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{Error as IOError, ErrorKind};
+use std::path::{Path, PathBuf};
+
+use nix::unistd::{getpid, getuid};
+
+use crate::config::Limits;
+use crate::user::{parse_bytes, parse_cpu};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+
+#[derive(Debug)]
+pub enum CgroupError {
+    /// The host does not use the unified (v2) hierarchy
+    NotUnified,
+    /// Unable to find a cgroup delegated to the user
+    NoDelegation,
+    /// The controller is not delegated to the user
+    Controller(&'static str),
+    /// A limit is not in the format of its cgroup file
+    Limit(&'static str, String),
+    IO(PathBuf, IOError),
+}
+
+impl fmt::Display for CgroupError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            CgroupError::NotUnified => write!(f, "limits require cgroup v2 (unified hierarchy)"),
+            CgroupError::NoDelegation => {
+                write!(
+                    f,
+                    "limits require a cgroup delegated to the user (systemd user session)"
+                )
+            }
+            CgroupError::Controller(controller) => write!(
+                f,
+                "the {} controller is not delegated to the user",
+                controller
+            ),
+            CgroupError::Limit(name, ref value) => {
+                write!(f, "invalid {} limit {:?}", name, value)
+            }
+            CgroupError::IO(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl Error for CgroupError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            CgroupError::IO(_, ref e) => Some(e),
+            CgroupError::NotUnified
+            | CgroupError::NoDelegation
+            | CgroupError::Controller(_)
+            | CgroupError::Limit(..) => None,
+        }
+    }
+}
+
+pub(crate) struct Session {
+    path: PathBuf,
+}
+
+impl Session {
+    /// Create the cgroup for the session, returns `None` if no limit is configured.
+    pub fn create(limits: &Limits) -> Result<Option<Self>, CgroupError> {
+        if limits.is_empty() {
+            return Ok(None);
+        }
+
+        // Nothing is created for limits the kernel would refuse
+        let values = values(limits)?;
+
+        let root = Path::new(CGROUP_ROOT);
+        if !root.join("cgroup.controllers").exists() {
+            return Err(CgroupError::NotUnified);
+        }
+
+        let current = read(Path::new("/proc/self/cgroup"))?;
+        let delegated = root.join(delegated_cgroup(&current).ok_or(CgroupError::NoDelegation)?);
+        let laurn = delegated.join("laurn");
+
+        match fs::create_dir(&laurn) {
+            Ok(_) => {}
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(CgroupError::IO(laurn, e)),
+        }
+        for controller in limits.controllers() {
+            // Controllers are enabled from the parent's subtree_control, all the way down from the
+            // delegated cgroup
+            for parent in [&delegated, &laurn].iter() {
+                write(
+                    &parent.join("cgroup.subtree_control"),
+                    &format!("+{}", controller),
+                )
+                .map_err(|_| CgroupError::Controller(controller))?;
+            }
+        }
+
+        let path = laurn.join(format!("session-{}", getpid()));
+        fs::create_dir(&path).map_err(|e| CgroupError::IO(path.clone(), e))?;
+        let session = Session { path };
+
+        for (file, value) in values.iter() {
+            write(&session.path.join(file), value)?;
+        }
+        if limits.memory.is_some() {
+            // Do not let the session escape its limit by swapping
+            write(&session.path.join("memory.swap.max"), "0").or_else(ignore_missing)?;
+        }
+
+        Ok(Some(session))
+    }
+
+    /// Move the current process (and its future children) into the session
+    pub fn enter(&self) -> Result<(), CgroupError> {
+        write(&self.path.join("cgroup.procs"), "0")
+    }
+
+    /// Whether the kernel OOM-killed a process of the session
+    pub fn oom_killed(&self) -> bool {
+        read(&self.path.join("memory.events"))
+            .map(|events| oom_kill_count(&events) > 0)
+            .unwrap_or(false)
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        // Only succeeds once every process of the session exited
+        let _ = fs::remove_dir(&self.path);
+    }
+}
+
+/// The cgroup delegated to the user, as `laurn doctor` reports it
+pub(crate) struct Delegation {
+    /// Controllers available in the delegated cgroup
+    pub controllers: Vec<String>,
+    /// Controllers enabled for the children of the delegated cgroup, and of `laurn/` if it
+    /// exists: the ones a session needs are enabled there when it is created
+    pub subtree_control: Vec<(PathBuf, Vec<String>)>,
+}
+
+pub(crate) fn delegation() -> Result<Delegation, CgroupError> {
+    let root = Path::new(CGROUP_ROOT);
+    if !root.join("cgroup.controllers").exists() {
+        return Err(CgroupError::NotUnified);
+    }
+
+    let current = read(Path::new("/proc/self/cgroup"))?;
+    let delegated = root.join(delegated_cgroup(&current).ok_or(CgroupError::NoDelegation)?);
+    let list = |path: &Path| -> Result<Vec<String>, CgroupError> {
+        Ok(read(path)?.split_whitespace().map(String::from).collect())
+    };
+
+    let controllers = list(&delegated.join("cgroup.controllers"))?;
+    let mut subtree_control = Vec::new();
+    for dir in [delegated.clone(), delegated.join("laurn")].iter() {
+        let path = dir.join("cgroup.subtree_control");
+        if dir != &delegated && !path.exists() {
+            continue;
+        }
+        let enabled = list(&path)?;
+        subtree_control.push((path, enabled));
+    }
+
+    Ok(Delegation {
+        controllers,
+        subtree_control,
+    })
+}
+
+/// Files of the session cgroup and the values written to them
+fn values(limits: &Limits) -> Result<Vec<(&'static str, String)>, CgroupError> {
+    let mut out = Vec::new();
+    if let Some(ref memory) = limits.memory {
+        let value = match parse_bytes(memory) {
+            Some(u64::MAX) => "max".to_string(),
+            Some(bytes) => bytes.to_string(),
+            None => return Err(CgroupError::Limit("memory", memory.clone())),
+        };
+        out.push(("memory.max", value));
+    }
+    if let Some(pids) = limits.pids {
+        out.push(("pids.max", pids.to_string()));
+    }
+    if let Some(ref cpu) = limits.cpu {
+        if parse_cpu(cpu).is_none() {
+            return Err(CgroupError::Limit("cpu", cpu.clone()));
+        }
+        out.push(("cpu.max", cpu.trim().to_string()));
+    }
+    Ok(out)
+}
+
+fn read(path: &Path) -> Result<String, CgroupError> {
+    fs::read_to_string(path).map_err(|e| CgroupError::IO(path.to_path_buf(), e))
+}
+
+fn write(path: &Path, value: &str) -> Result<(), CgroupError> {
+    fs::write(path, value.as_bytes()).map_err(|e| CgroupError::IO(path.to_path_buf(), e))
+}
+
+fn ignore_missing(e: CgroupError) -> Result<(), CgroupError> {
+    match e {
+        CgroupError::IO(_, ref io) if io.kind() == ErrorKind::NotFound => Ok(()),
+        e => Err(e),
+    }
+}
+
+/// Find the cgroup systemd delegated to the user from the content of `/proc/self/cgroup`
+///
+/// Returns it relative to the cgroup root.
+fn delegated_cgroup(proc_cgroup: &str) -> Option<PathBuf> {
+    let service = format!("user@{}.service", getuid());
+    delegated_cgroup_for(proc_cgroup, &service)
+}
+
+fn delegated_cgroup_for(proc_cgroup: &str, service: &str) -> Option<PathBuf> {
+    // The unified hierarchy is the line formatted as "0::/path"
+    let current = proc_cgroup
+        .lines()
+        .find_map(|line| line.strip_prefix("0::"))?;
+
+    let mut out = PathBuf::new();
+    for part in Path::new(current).iter().skip(1) {
+        out.push(part);
+        if part == service {
+            return Some(out);
+        }
+    }
+    None
+}
+
+fn oom_kill_count(events: &str) -> u64 {
+    events
+        .lines()
+        .find_map(|line| line.strip_prefix("oom_kill "))
+        .and_then(|count| count.trim().parse().ok())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delegated() {
+        let input = "0::/user.slice/user-1000.slice/user@1000.service/app.slice/vte-spawn.scope\n";
+        assert_eq!(
+            delegated_cgroup_for(input, "user@1000.service"),
+            Some(PathBuf::from(
+                "user.slice/user-1000.slice/user@1000.service"
+            ))
+        );
+
+        let input = "0::/user.slice/user-1000.slice/session-2.scope\n";
+        assert_eq!(delegated_cgroup_for(input, "user@1000.service"), None);
+    }
+
+    #[test]
+    fn limits() {
+        let limits = Limits {
+            memory: Some("512M".to_string()),
+            pids: Some(64),
+            cpu: Some("50000 100000".to_string()),
+        };
+        assert_eq!(
+            values(&limits).unwrap(),
+            vec![
+                ("memory.max", (512u64 << 20).to_string()),
+                ("pids.max", "64".to_string()),
+                ("cpu.max", "50000 100000".to_string()),
+            ]
+        );
+
+        let limits = Limits {
+            memory: Some("1G\nmemory.high".to_string()),
+            ..Limits::default()
+        };
+        assert!(matches!(
+            values(&limits),
+            Err(CgroupError::Limit("memory", _))
+        ));
+        let limits = Limits {
+            cpu: Some("-1 100000".to_string()),
+            ..Limits::default()
+        };
+        assert!(matches!(values(&limits), Err(CgroupError::Limit("cpu", _))));
+    }
+
+    #[test]
+    fn oom_kill() {
+        let events = "low 0\nhigh 0\nmax 12\noom 1\noom_kill 1\n";
+        assert_eq!(oom_kill_count(events), 1);
+        assert_eq!(oom_kill_count("low 0\n"), 0);
+    }
+}
@@ -1,81 +1,862 @@
 /// parse the `laurn.nix` config file
 ///
-use std::fs::File;
-use std::io::{self, Read};
-use std::path::Path;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
 
-use serde_derive::Deserialize;
+use home::home_dir;
+
+use crate::user::{Session, UserConfig};
+
+use serde::{Deserialize as _, Deserializer};
+use serde_derive::{Deserialize, Serialize};
 
 #[derive(Debug)]
 pub enum ConfigError {
     IO(io::Error),
-    Parsing(toml::de::Error),
+    /// The file, merged with the ones it inherits, is not a valid configuration
+    Parsing(PathBuf, toml::de::Error),
+    /// No `.laurnrc` in the directory or any of its parents
+    NotFound(PathBuf),
+    Invalid(Invalid),
+    /// The file declares a version of the configuration newer than this laurn understands
+    UnsupportedVersion(PathBuf, i64),
+}
+
+/// An error in a configuration file, located in the file
+#[derive(Debug, PartialEq, Eq)]
+pub struct Invalid {
+    pub path: PathBuf,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+    /// The closest known key or value, for typos
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for Invalid {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.path.display(),
+            self.line,
+            self.column,
+            self.message
+        )?;
+        if let Some(ref suggestion) = self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ConfigError::IO(ref e) => write!(f, "unable to read the configuration: {}", e),
+            ConfigError::Parsing(ref path, ref e) => {
+                write!(f, "{}: invalid configuration: {}", path.display(), e)
+            }
+            ConfigError::NotFound(ref dir) => write!(
+                f,
+                "no {} in {} or its parents",
+                CONFIG_FILE,
+                dir.display()
+            ),
+            ConfigError::Invalid(ref invalid) => write!(f, "{}", invalid),
+            ConfigError::UnsupportedVersion(ref path, version) => write!(
+                f,
+                "{}: version {} of the configuration requires a newer laurn (this one understands {})",
+                path.display(),
+                version,
+                CONFIG_VERSION
+            ),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            ConfigError::IO(ref e) => Some(e),
+            ConfigError::Parsing(_, ref e) => Some(e),
+            ConfigError::NotFound(_)
+            | ConfigError::Invalid(_)
+            | ConfigError::UnsupportedVersion(..) => None,
+        }
+    }
 }
 
-#[derive(Deserialize, Debug)]
+/// Version of the configuration format understood by this laurn
+pub const CONFIG_VERSION: i64 = 1;
+
+/// The configuration file, marking the root of a project
+pub const CONFIG_FILE: &str = ".laurnrc";
+
+/// The nix environment of a project
+pub const NIX_FILE: &str = "laurn.nix";
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
+    /// Version of the configuration format, see `CONFIG_VERSION`
+    #[serde(default)]
+    pub version: Option<i64>,
+    #[serde(default)]
     pub laurn: LaurnConfig,
+    #[serde(default)]
+    pub limits: Limits,
+    #[serde(default)]
+    pub rlimits: Rlimits,
+    #[serde(default)]
+    pub masking: Masking,
+    #[serde(default)]
+    pub tmp: Tmp,
+    /// Only ever set from the configuration of the user
+    #[serde(skip_deserializing)]
+    pub session: Session,
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            laurn: LaurnConfig::default(),
-        }
+impl Config {
+    /// The most restrictive configuration, for projects the user did not allow
+    pub fn restricted() -> Self {
+        let mut config = Config::default();
+        config.laurn.project = ProjectMode::ReadOnly;
+        config
     }
 }
 
-#[derive(Deserialize, Debug)]
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
 pub struct LaurnConfig {
-    pub mode: Mode,
+    /// Either a single mode (`mode = "rust"`) or a list (`mode = ["rust", "node"]`)
+    #[serde(default = "default_mode", deserialize_with = "one_or_many")]
+    pub mode: Vec<Mode>,
     #[serde(default)]
     pub network: NetworkConfig,
+    /// What to do when a path is exposed read-only by a mode and read-write by another
+    #[serde(default)]
+    pub conflict: ConflictPolicy,
+    /// Patterns of files of the project to hide from the sandbox (`**/.env`, `secrets/`, ...)
+    #[serde(default)]
+    pub hide: Vec<String>,
+    #[serde(default)]
+    pub project: ProjectMode,
+    /// Directories of the project writable from the sandbox, when the project is read-only
+    #[serde(default)]
+    pub writable: Vec<PathBuf>,
+    /// Paths of the project to expose read-only, in addition to `.git`, `.laurnrc`, `laurn.nix`
+    /// and `nix`
+    #[serde(default)]
+    pub protect: Vec<PathBuf>,
+    /// Paths of the project to remove from the protected paths
+    #[serde(default)]
+    pub unprotect: Vec<PathBuf>,
+    /// Persistent volumes managed by laurn, by name, with where to mount them
+    #[serde(default)]
+    pub volumes: BTreeMap<String, String>,
+    /// Merge this configuration on top of the `.laurnrc` of a parent directory
+    #[serde(default)]
+    pub inherit: bool,
+    /// Mount the whole directory, sub-projects only select their own `laurn.nix`
+    #[serde(default)]
+    pub workspace: bool,
 }
 
 impl Default for LaurnConfig {
     fn default() -> Self {
         LaurnConfig {
-            mode: Mode::None,
+            mode: default_mode(),
             network: NetworkConfig::Isolated,
+            conflict: ConflictPolicy::ReadWrite,
+            hide: Vec::new(),
+            project: ProjectMode::ReadWrite,
+            writable: Vec::new(),
+            protect: Vec::new(),
+            unprotect: Vec::new(),
+            volumes: BTreeMap::new(),
+            inherit: false,
+            workspace: false,
         }
     }
 }
 
-#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+fn default_mode() -> Vec<Mode> {
+    vec![Mode::None]
+}
+
+fn one_or_many<'de, D>(deserializer: D) -> Result<Vec<Mode>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany {
+        One(Mode),
+        Many(Vec<Mode>),
+    }
+
+    match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(mode) => Ok(vec![mode]),
+        OneOrMany::Many(modes) => Ok(modes),
+    }
+}
+
+/// How the project directory is mounted in the sandbox
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+pub enum ProjectMode {
+    #[default]
+    #[serde(rename = "rw")]
+    ReadWrite,
+    #[serde(rename = "ro")]
+    ReadOnly,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ConflictPolicy {
+    /// Read-write access wins
+    #[default]
+    #[serde(rename = "rw")]
+    ReadWrite,
+    /// Refuse to start
+    Error,
+}
+
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum NetworkConfig {
+    #[default]
     Isolated,
     Exposed,
 }
 
-impl Default for NetworkConfig {
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    None,
+    Rust,
+    /// Rust toolchains managed by rustup, read-only
+    Rustup,
+    Node,
+    Python,
+    Go,
+    Jvm,
+    Haskell,
+}
+
+/// Every mode, in the order they are written
+pub const MODES: &[Mode] = &[
+    Mode::None,
+    Mode::Rust,
+    Mode::Rustup,
+    Mode::Node,
+    Mode::Python,
+    Mode::Go,
+    Mode::Jvm,
+    Mode::Haskell,
+];
+
+/// Name of the mode in the configuration
+pub fn mode_name(mode: Mode) -> &'static str {
+    match mode {
+        Mode::None => "none",
+        Mode::Rust => "rust",
+        Mode::Rustup => "rustup",
+        Mode::Node => "node",
+        Mode::Python => "python",
+        Mode::Go => "go",
+        Mode::Jvm => "jvm",
+        Mode::Haskell => "haskell",
+    }
+}
+
+pub fn parse_mode(name: &str) -> Option<Mode> {
+    MODES.iter().cloned().find(|mode| mode_name(*mode) == name)
+}
+
+/// Resource limits of the session, applied through cgroup v2
+///
+/// Values are in the format of the cgroup files (`memory.max`, `pids.max` and `cpu.max`), they are
+/// checked before being written.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Limits {
+    pub memory: Option<String>,
+    pub pids: Option<u64>,
+    pub cpu: Option<String>,
+}
+
+impl Limits {
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_none() && self.pids.is_none() && self.cpu.is_none()
+    }
+
+    /// cgroup controllers required to enforce the limits
+    pub(crate) fn controllers(&self) -> Vec<&'static str> {
+        let mut out = Vec::new();
+        if self.memory.is_some() {
+            out.push("memory");
+        }
+        if self.pids.is_some() {
+            out.push("pids");
+        }
+        if self.cpu.is_some() {
+            out.push("cpu");
+        }
+        out
+    }
+}
+
+/// Per-process limits (setrlimit(2)) applied to the processes of the sandbox
+///
+/// Both soft and hard limits are set, processes can not raise them back. A limit above the hard
+/// limit laurn runs with is lowered to it.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Rlimits {
+    pub nofile: Option<Rlimit>,
+    /// Maximum size of core dumps, disabled by default so that a crash does not leave a file
+    /// with the memory of the process (and its secrets) in the project directory.
+    pub core: Option<Rlimit>,
+    pub nproc: Option<Rlimit>,
+    #[serde(rename = "as")]
+    pub address_space: Option<Rlimit>,
+    /// CPU time, in seconds
+    pub cpu: Option<Rlimit>,
+}
+
+impl Default for Rlimits {
     fn default() -> Self {
-        Self::Isolated
+        Rlimits {
+            nofile: None,
+            core: Some(Rlimit::Limit(0)),
+            nproc: None,
+            address_space: None,
+            cpu: None,
+        }
+    }
+}
+
+/// Value of an rlimit, a number or `"unlimited"`
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Copy, Clone)]
+#[serde(try_from = "RlimitValue", into = "RlimitValue")]
+pub enum Rlimit {
+    Limit(u64),
+    /// As high as the hard limit of laurn allows
+    Unlimited,
+}
+
+#[derive(Deserialize, Serialize, Clone)]
+#[serde(untagged)]
+enum RlimitValue {
+    Limit(u64),
+    Name(String),
+}
+
+impl TryFrom<RlimitValue> for Rlimit {
+    type Error = String;
+
+    fn try_from(value: RlimitValue) -> Result<Self, Self::Error> {
+        match value {
+            RlimitValue::Limit(limit) => Ok(Rlimit::Limit(limit)),
+            RlimitValue::Name(ref name) if name == "unlimited" => Ok(Rlimit::Unlimited),
+            RlimitValue::Name(name) => Err(format!(
+                "invalid rlimit {:?}, expected a number or \"unlimited\"",
+                name
+            )),
+        }
+    }
+}
+
+impl From<Rlimit> for RlimitValue {
+    fn from(rlimit: Rlimit) -> Self {
+        match rlimit {
+            Rlimit::Limit(limit) => RlimitValue::Limit(limit),
+            Rlimit::Unlimited => RlimitValue::Name("unlimited".to_string()),
+        }
+    }
+}
+
+/// Kernel interfaces hidden from the sandbox (equivalent of OCI's masked paths)
+const DEFAULT_MASKED_PATHS: &[&str] = &[
+    "/proc/acpi",
+    "/proc/asound",
+    "/proc/kallsyms",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/sys/firmware",
+    "/sys/devices/virtual/powercap",
+];
+
+/// Kernel interfaces exposed read-only to the sandbox
+const DEFAULT_READONLY_PATHS: &[&str] = &[
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// Masking of sensitive entries of `/proc` and `/sys`
+///
+/// Masked files get `/dev/null` mounted over them, and masked directories an empty read-only tmpfs.
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone, Default)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Masking {
+    pub sys: SysMode,
+    /// Masked in addition to the defaults
+    pub masked_paths: Vec<PathBuf>,
+    /// Read-only in addition to the defaults
+    pub readonly_paths: Vec<PathBuf>,
+    /// Removed from the default masked and read-only paths
+    pub unmasked_paths: Vec<PathBuf>,
+}
+
+impl Masking {
+    pub fn masked(&self) -> Vec<PathBuf> {
+        self.with_defaults(DEFAULT_MASKED_PATHS, &self.masked_paths)
+    }
+
+    pub fn readonly(&self) -> Vec<PathBuf> {
+        self.with_defaults(DEFAULT_READONLY_PATHS, &self.readonly_paths)
+    }
+
+    fn with_defaults(&self, defaults: &[&str], extra: &[PathBuf]) -> Vec<PathBuf> {
+        defaults
+            .iter()
+            .map(PathBuf::from)
+            .filter(|p| !self.unmasked_paths.contains(p))
+            .chain(extra.iter().cloned())
+            .collect()
     }
 }
 
-#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone)]
+/// How `/sys` is exposed to the sandbox
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
 #[serde(rename_all = "lowercase")]
-pub enum Mode {
-    None,
-    Rust,
+pub enum SysMode {
+    #[default]
+    #[serde(rename = "ro")]
+    ReadOnly,
+    #[serde(rename = "rw")]
+    ReadWrite,
+    /// Only an empty directory
+    Empty,
+}
+
+/// Private tmpfs mounted on `/tmp`, `/var/tmp` and `/run`
+#[derive(Deserialize, Serialize, PartialEq, Eq, Debug, Clone)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Tmp {
+    /// Size limit of each tmpfs, in bytes with an optional K, M, G or T suffix
+    pub size: String,
+    /// Keep `/tmp` between sessions, stored per-project in laurn's data directory
+    ///
+    /// The persistent `/tmp` is a directory on the disk, `size` does not limit it.
+    pub persist: bool,
+}
+
+impl Default for Tmp {
+    fn default() -> Self {
+        Tmp {
+            size: "512m".to_string(),
+            persist: false,
+        }
+    }
 }
 
 pub fn load_config(path: &Path) -> Result<Config, ConfigError> {
-    let mut file = File::open(path).map_err(ConfigError::IO)?;
-    let mut contents = String::new();
-    file.read_to_string(&mut contents)
-        .map_err(ConfigError::IO)?;
-    load_config_str(&contents)
+    let value = read_value(path)?;
+    value
+        .try_into()
+        .map_err(|e| ConfigError::Parsing(path.to_path_buf(), e))
+}
+
+/// Find the root of the project `start` is part of, the closest directory with a `.laurnrc`
+///
+/// The search neither goes above the home directory nor crosses filesystems.
+pub fn find_project_root(start: &Path) -> Result<PathBuf, ConfigError> {
+    let home = home_dir();
+    find_project_root_in(start, home.as_deref())
 }
 
+fn find_project_root_in(start: &Path, home: Option<&Path>) -> Result<PathBuf, ConfigError> {
+    let device = fs::metadata(start).map_err(ConfigError::IO)?.dev();
+
+    for dir in start.ancestors() {
+        let metadata = match fs::metadata(dir) {
+            Ok(metadata) => metadata,
+            Err(_) => break,
+        };
+        if metadata.dev() != device {
+            break;
+        }
+        if dir.join(CONFIG_FILE).is_file() {
+            return Ok(dir.to_path_buf());
+        }
+        if Some(dir) == home {
+            break;
+        }
+    }
+
+    Err(ConfigError::NotFound(start.to_path_buf()))
+}
+
+/// A project found from a directory, with its configuration merged with the ones it inherits
+#[derive(Debug)]
+pub struct Project {
+    /// The directory mounted in the sandbox
+    pub root: PathBuf,
+    /// The `laurn.nix` describing the environment
+    pub nix_expr: PathBuf,
+    /// Directories of the `.laurnrc` files read, from the closest one
+    pub config_dirs: Vec<PathBuf>,
+    pub config: Config,
+    /// What the policy of the user changed in the configuration
+    pub enforced: Vec<String>,
+    /// Where each value of the configuration comes from
+    pub origins: Origins,
+}
+
+/// Origin of each value of the configuration, by key (`laurn.network`)
+pub type Origins = BTreeMap<String, String>;
+
+impl Project {
+    /// Change the configuration, what changed is recorded as coming from `origin`
+    pub fn amend<F, R>(&mut self, origin: &str, change: F) -> R
+    where
+        F: FnOnce(&mut Config) -> R,
+    {
+        let before = toml::Value::try_from(&self.config).ok();
+        let out = change(&mut self.config);
+        let after = toml::Value::try_from(&self.config).ok();
+
+        if let (Some(before), Some(after)) = (before, after) {
+            let before = leaves(&before);
+            let after = leaves(&after);
+            for (key, value) in after.iter() {
+                if before.get(key) != Some(value) {
+                    self.origins.insert(key.clone(), origin.to_string());
+                }
+            }
+            self.origins.retain(|key, _| after.contains_key(key));
+        }
+
+        out
+    }
+}
+
+/// Values of the configuration by key, tables nested in sections are a single value
+fn leaves(value: &toml::Value) -> BTreeMap<String, &toml::Value> {
+    let mut out = BTreeMap::new();
+    if let Some(table) = value.as_table() {
+        for (name, value) in table.iter() {
+            match value.as_table() {
+                Some(section) => {
+                    for (key, value) in section.iter() {
+                        out.insert(format!("{}.{}", name, key), value);
+                    }
+                }
+                None => {
+                    out.insert(name.clone(), value);
+                }
+            }
+        }
+    }
+    out
+}
+
+fn record_origins(value: &toml::Value, origin: &str, origins: &mut Origins) {
+    for key in leaves(value).keys() {
+        origins.insert(key.clone(), origin.to_string());
+    }
+}
+
+/// Find the project `start` is part of and load its configuration
+///
+/// A `.laurnrc` with `inherit = true` is merged field by field on top of the `.laurnrc` of a
+/// parent directory. When the configuration sets `workspace = true`, the directory of the
+/// outermost `.laurnrc` is mounted and the closest `laurn.nix` selects the environment.
+///
+/// The defaults of the user are overridden by the project, its policy overrides the project.
+pub fn load_project(start: &Path, user: &UserConfig) -> Result<Project, ConfigError> {
+    let home = home_dir();
+    let mut project = load_project_in(start, home.as_deref(), user.defaults.as_ref())?;
+    let root = project.root.clone();
+    project.enforced = project.amend("user policy", |config| user.enforce(&root, config));
+    Ok(project)
+}
+
+fn load_project_in(
+    start: &Path,
+    home: Option<&Path>,
+    defaults: Option<&toml::Value>,
+) -> Result<Project, ConfigError> {
+    let mut dir = find_project_root_in(start, home)?;
+    let mut value = read_value(&dir.join(CONFIG_FILE))?;
+    let mut config_dirs = vec![dir.clone()];
+    let mut layers = vec![value.clone()];
+
+    while inherits(&value) {
+        let parent = match dir.parent() {
+            Some(parent) => parent.to_path_buf(),
+            None => return Err(ConfigError::NotFound(dir)),
+        };
+        dir = find_project_root_in(&parent, home)?;
+        let mut base = read_value(&dir.join(CONFIG_FILE))?;
+        layers.push(base.clone());
+        let parent_inherits = inherits(&base);
+        merge_values(&mut base, value);
+        value = base;
+        // Whether to keep going only depends on the parent itself
+        set_inherit(&mut value, parent_inherits);
+        config_dirs.push(dir.clone());
+    }
+
+    if let Some(defaults) = defaults {
+        let mut base = defaults.clone();
+        merge_values(&mut base, value);
+        value = base;
+    }
+    let config: Config = value
+        .try_into()
+        .map_err(|e| ConfigError::Parsing(config_dirs[0].join(CONFIG_FILE), e))?;
+
+    let mut origins = Origins::new();
+    if let Ok(default) = toml::Value::try_from(Config::default()) {
+        record_origins(&default, "default", &mut origins);
+    }
+    if let Some(defaults) = defaults {
+        record_origins(defaults, "user defaults", &mut origins);
+    }
+    for (dir, layer) in config_dirs.iter().zip(layers.iter()).rev() {
+        let origin = dir.join(CONFIG_FILE);
+        record_origins(layer, &origin.to_string_lossy(), &mut origins);
+    }
+
+    let (root, nix_expr) = if config.laurn.workspace {
+        let root = config_dirs[config_dirs.len() - 1].clone();
+        let nix_expr = start
+            .ancestors()
+            .take_while(|dir| dir.starts_with(&root))
+            .map(|dir| dir.join(NIX_FILE))
+            .find(|nix_expr| nix_expr.is_file())
+            .unwrap_or_else(|| root.join(NIX_FILE));
+        (root, nix_expr)
+    } else {
+        let root = config_dirs[0].clone();
+        let nix_expr = root.join(NIX_FILE);
+        (root, nix_expr)
+    };
+
+    Ok(Project {
+        root,
+        nix_expr,
+        config_dirs,
+        config,
+        enforced: Vec::new(),
+        origins,
+    })
+}
+
+/// Read and validate a configuration file
+fn read_value(path: &Path) -> Result<toml::Value, ConfigError> {
+    let contents = fs::read_to_string(path).map_err(ConfigError::IO)?;
+    parse_value(path, &contents)
+}
+
+fn parse_value(path: &Path, contents: &str) -> Result<toml::Value, ConfigError> {
+    let value: toml::Value =
+        toml::from_str(contents).map_err(|e| ConfigError::Invalid(invalid(path, contents, &e)))?;
+
+    // Checked first, a newer configuration is likely to have keys we do not know about
+    if let Some(version) = value.get("version").and_then(toml::Value::as_integer) {
+        if version > CONFIG_VERSION {
+            return Err(ConfigError::UnsupportedVersion(path.to_path_buf(), version));
+        }
+    }
+
+    match value.clone().try_into::<Config>() {
+        Ok(_) => Ok(value),
+        // Only errors from the text name the table of the key, parse it again to report them
+        Err(e) => {
+            let e = toml::from_str::<Config>(contents).err().unwrap_or(e);
+            Err(ConfigError::Invalid(invalid(path, contents, &e)))
+        }
+    }
+}
+
+/// Locate the error in the file, and look for a suggestion if it is about an unknown key or value
+pub(crate) fn invalid(path: &Path, contents: &str, error: &toml::de::Error) -> Invalid {
+    let message = error.to_string();
+    // The location is part of the Invalid error itself
+    let message = match message.rfind(" at line ") {
+        Some(i) => message[..i].to_string(),
+        None => message,
+    };
+
+    // serde quotes the unknown name, then the expected ones
+    let quoted: Vec<&str> = message.split('`').skip(1).step_by(2).collect();
+    let unknown = message.starts_with("unknown field") || message.starts_with("unknown variant");
+    let suggestion = match quoted.split_first() {
+        Some((name, expected)) if unknown => closest(name, expected).map(String::from),
+        _ => None,
+    };
+
+    // Unknown keys are reported at the start of the file, look for them in their table
+    let table = message
+        .rfind("for key `")
+        .map(|i| message[i + "for key `".len()..].trim_end_matches('`'));
+    let key = match table {
+        Some(table) if message.starts_with("unknown variant") => Some(table.to_string()),
+        Some(table) if message.starts_with("unknown field") => {
+            quoted.first().map(|field| format!("{}.{}", table, field))
+        }
+        None if message.starts_with("unknown field") => quoted.first().map(|f| f.to_string()),
+        _ => None,
+    };
+    let (line, column) = match (key.and_then(|key| locate(contents, &key)), error.line_col()) {
+        (Some(position), _) => position,
+        (None, Some((line, column))) => (line + 1, column + 1),
+        (None, None) => (1, 1),
+    };
+
+    Invalid {
+        path: path.to_path_buf(),
+        line,
+        column,
+        message,
+        suggestion,
+    }
+}
+
+/// Line and column (from 1) where the key (`laurn.mode`) is defined
+///
+/// The key may also be found in a parent table (`defaults.laurn.mode` in the user
+/// configuration) when it is not defined at this exact place.
+fn locate(contents: &str, key: &str) -> Option<(usize, usize)> {
+    let key = split_key(key);
+    let mut table: Vec<String> = Vec::new();
+    let mut exact = None;
+    let mut nested = None;
+    for (i, line) in contents.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let path = if trimmed.starts_with('[') {
+            let name = trimmed
+                .trim_start_matches('[')
+                .split(']')
+                .next()
+                .unwrap_or("");
+            table = split_key(name);
+            table.clone()
+        } else if !trimmed.starts_with('#') && trimmed.contains('=') {
+            let name = trimmed.split('=').next().unwrap_or("");
+            table.iter().cloned().chain(split_key(name)).collect()
+        } else {
+            continue;
+        };
+
+        let position = (i + 1, line.len() - trimmed.len() + 1);
+        if path == key {
+            exact = exact.or(Some(position));
+        } else if path.ends_with(&key) {
+            nested = nested.or(Some(position));
+        }
+    }
+    exact.or(nested)
+}
+
+/// Parts of a dotted key, `laurn."mode"` is `["laurn", "mode"]`
+fn split_key(key: &str) -> Vec<String> {
+    key.split('.')
+        .map(|part| part.trim().trim_matches('"').to_string())
+        .collect()
+}
+
+/// The expected name the closest to the unknown one, if close enough to be a typo
+fn closest<'a>(unknown: &str, expected: &[&'a str]) -> Option<&'a str> {
+    expected
+        .iter()
+        .map(|candidate| (distance(unknown, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= cmp::max(2, unknown.len() / 3))
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+/// Levenshtein distance
+fn distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + if ca == *cb { 0 } else { 1 };
+            current.push(cmp::min(
+                substitution,
+                cmp::min(previous[j + 1] + 1, current[j] + 1),
+            ));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+fn inherits(value: &toml::Value) -> bool {
+    value
+        .get("laurn")
+        .and_then(|laurn| laurn.get("inherit"))
+        .and_then(toml::Value::as_bool)
+        .unwrap_or(false)
+}
+
+fn set_inherit(value: &mut toml::Value, inherit: bool) {
+    if let Some(laurn) = value.get_mut("laurn").and_then(toml::Value::as_table_mut) {
+        laurn.insert("inherit".to_string(), toml::Value::Boolean(inherit));
+    }
+}
+
+/// Tables are merged key by key, anything else in `other` replaces the value of `base`
+fn merge_values(base: &mut toml::Value, other: toml::Value) {
+    match (base, other) {
+        (toml::Value::Table(base), toml::Value::Table(other)) => {
+            for (key, value) in other {
+                match base.get_mut(&key) {
+                    Some(existing) => merge_values(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, other) => *base = other,
+    }
+}
+
+#[cfg(test)]
 fn load_config_str(contents: &str) -> Result<Config, ConfigError> {
-    toml::from_str(&contents).map_err(ConfigError::Parsing)
+    toml::from_str(contents).map_err(|e| ConfigError::Parsing(PathBuf::from(CONFIG_FILE), e))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::Builder as TempBuilder;
     #[test]
     fn simple() {
         let config = load_config_str(
@@ -87,6 +868,410 @@ mode = "rust"
 
         let config = config.expect("unable to parse config");
 
-        assert_eq!(config.laurn.mode, Mode::Rust);
+        assert_eq!(config.laurn.mode, vec![Mode::Rust]);
+        assert_eq!(config.laurn.conflict, ConflictPolicy::ReadWrite);
+        assert_eq!(config.laurn.project, ProjectMode::ReadWrite);
+        assert!(config.laurn.volumes.is_empty());
+        assert!(config.limits.is_empty());
+        assert_eq!(config.rlimits, Rlimits::default());
+        assert_eq!(config.rlimits.core, Some(Rlimit::Limit(0)));
+        assert_eq!(config.masking.sys, SysMode::ReadOnly);
+        assert_eq!(config.tmp, Tmp::default());
+    }
+
+    #[test]
+    fn tmp() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "none"
+
+[tmp]
+size = "2g"
+persist = true
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(config.tmp.size, "2g");
+        assert!(config.tmp.persist);
+    }
+
+    #[test]
+    fn rlimits() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "none"
+
+[rlimits]
+nofile = 4096
+as = 8589934592
+core = "unlimited"
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(config.rlimits.nofile, Some(Rlimit::Limit(4096)));
+        assert_eq!(
+            config.rlimits.address_space,
+            Some(Rlimit::Limit(8589934592))
+        );
+        assert_eq!(config.rlimits.core, Some(Rlimit::Unlimited));
+        assert_eq!(config.rlimits.nproc, None);
+
+        let config = load_config_str("[laurn]\nmode = \"none\"\n\n[rlimits]\ncore = \"none\"\n");
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn masking() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "none"
+
+[masking]
+sys = "empty"
+masked_paths = ["/proc/cpuinfo"]
+unmasked_paths = ["/proc/asound", "/proc/sys"]
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(config.masking.sys, SysMode::Empty);
+        let masked = config.masking.masked();
+        assert!(!masked.contains(&PathBuf::from("/proc/asound")));
+        assert!(masked.contains(&PathBuf::from("/proc/kallsyms")));
+        assert!(masked.contains(&PathBuf::from("/proc/cpuinfo")));
+        let readonly = config.masking.readonly();
+        assert!(!readonly.contains(&PathBuf::from("/proc/sys")));
+        assert!(readonly.contains(&PathBuf::from("/proc/sysrq-trigger")));
+    }
+
+    #[test]
+    fn mode_names() {
+        for mode in MODES.iter() {
+            assert_eq!(
+                toml::Value::try_from(mode),
+                Ok(toml::Value::String(mode_name(*mode).to_string()))
+            );
+        }
+        assert_eq!(parse_mode("python"), Some(Mode::Python));
+        assert_eq!(parse_mode("cobol"), None);
+    }
+
+    #[test]
+    fn multiple_modes() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = ["rust", "node"]
+conflict = "error"
+hide = ["**/.env", "secrets/"]
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(config.laurn.mode, vec![Mode::Rust, Mode::Node]);
+        assert_eq!(config.laurn.conflict, ConflictPolicy::Error);
+        assert_eq!(config.laurn.hide, vec!["**/.env", "secrets/"]);
+    }
+
+    #[test]
+    fn read_only_project() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "node"
+project = "ro"
+writable = ["target/", "node_modules/"]
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(config.laurn.project, ProjectMode::ReadOnly);
+        assert_eq!(
+            config.laurn.writable,
+            vec![PathBuf::from("target"), PathBuf::from("node_modules")]
+        );
+    }
+
+    #[test]
+    fn protect() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "none"
+protect = ["flake.nix", ".github/workflows"]
+unprotect = [".git"]
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(
+            config.laurn.protect,
+            vec![
+                PathBuf::from("flake.nix"),
+                PathBuf::from(".github/workflows")
+            ]
+        );
+        assert_eq!(config.laurn.unprotect, vec![PathBuf::from(".git")]);
+    }
+
+    #[test]
+    fn volumes() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "rust"
+volumes = { cargo = "~/.cargo/registry", target = "target" }
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(
+            config.laurn.volumes.get("cargo").map(String::as_str),
+            Some("~/.cargo/registry")
+        );
+        assert_eq!(
+            config.laurn.volumes.get("target").map(String::as_str),
+            Some("target")
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let config = load_config_str(
+            r#"
+[laurn]
+mode = "none"
+
+[limits]
+memory = "4G"
+pids = 1024
+cpu = "200000 100000"
+"#,
+        );
+
+        let config = config.expect("unable to parse config");
+
+        assert_eq!(config.limits.memory.as_deref(), Some("4G"));
+        assert_eq!(config.limits.pids, Some(1024));
+        assert_eq!(config.limits.cpu.as_deref(), Some("200000 100000"));
+        assert_eq!(config.limits.controllers(), vec!["memory", "pids", "cpu"]);
+    }
+
+    #[test]
+    fn project_root() {
+        let dir = TempBuilder::new().tempdir().expect("tempdir");
+        let root = dir.path().join("project");
+        let src = root.join("src/bin");
+        fs::create_dir_all(&src).unwrap();
+        fs::write(root.join(CONFIG_FILE), b"[laurn]\n").unwrap();
+
+        assert_eq!(find_project_root_in(&src, None).unwrap(), root);
+        assert_eq!(find_project_root_in(&root, None).unwrap(), root);
+        // The home directory is a boundary
+        assert!(find_project_root_in(&src, Some(&root.join("src"))).is_err());
+        assert!(find_project_root_in(dir.path(), None).is_err());
+    }
+
+    #[test]
+    fn workspace() {
+        let dir = TempBuilder::new().tempdir().expect("tempdir");
+        let root = dir.path().join("repo");
+        let api = root.join("services/api");
+        let web = root.join("services/web");
+        fs::create_dir_all(api.join("src")).unwrap();
+        fs::create_dir_all(web.join("src")).unwrap();
+        fs::write(
+            root.join(CONFIG_FILE),
+            "[laurn]\nmode = \"rust\"\nworkspace = true\nhide = [\"**/.env\"]\n\n[limits]\npids = 512\n",
+        )
+        .unwrap();
+        fs::write(root.join(NIX_FILE), "").unwrap();
+        fs::write(api.join(NIX_FILE), "").unwrap();
+        fs::write(
+            web.join(CONFIG_FILE),
+            "[laurn]\ninherit = true\nmode = \"node\"\n\n[limits]\nmemory = \"2G\"\n",
+        )
+        .unwrap();
+        fs::write(web.join(NIX_FILE), "").unwrap();
+
+        // No configuration of its own, only the environment is selected
+        let project = load_project_in(&api.join("src"), None, None).expect("api project");
+        assert_eq!(project.root, root);
+        assert_eq!(project.nix_expr, api.join(NIX_FILE));
+        assert_eq!(project.config.laurn.mode, vec![Mode::Rust]);
+
+        // Merged field by field on top of the root configuration
+        let project = load_project_in(&web.join("src"), None, None).expect("web project");
+        assert_eq!(project.root, root);
+        assert_eq!(project.nix_expr, web.join(NIX_FILE));
+        assert_eq!(project.config_dirs, vec![web.clone(), root.clone()]);
+        assert_eq!(project.config.laurn.mode, vec![Mode::Node]);
+        assert_eq!(project.config.laurn.hide, vec!["**/.env".to_string()]);
+        assert_eq!(project.config.limits.pids, Some(512));
+        assert_eq!(project.config.limits.memory, Some("2G".to_string()));
+
+        // Without the workspace, the sub-project is mounted on its own
+        fs::write(
+            web.join(CONFIG_FILE),
+            "[laurn]\ninherit = true\nworkspace = false\n",
+        )
+        .unwrap();
+        let project = load_project_in(&web, None, None).expect("web project");
+        assert_eq!(project.root, web);
+        assert_eq!(project.config.laurn.mode, vec![Mode::Rust]);
+    }
+
+    #[test]
+    fn user_defaults() {
+        let dir = TempBuilder::new().tempdir().expect("tempdir");
+        fs::write(
+            dir.path().join(CONFIG_FILE),
+            "[laurn]\nmode = \"node\"\n\n[limits]\npids = 128\n",
+        )
+        .unwrap();
+        let defaults: toml::Value = toml::from_str(
+            "[laurn]\nmode = \"rust\"\nhide = [\"**/.env\"]\n\n[limits]\npids = 512\n",
+        )
+        .unwrap();
+
+        let project = load_project_in(dir.path(), None, Some(&defaults)).expect("project");
+        assert_eq!(project.config.laurn.mode, vec![Mode::Node]);
+        assert_eq!(project.config.laurn.hide, vec!["**/.env".to_string()]);
+        assert_eq!(project.config.limits.pids, Some(128));
+    }
+
+    #[test]
+    fn unknown_keys() {
+        let path = Path::new(".laurnrc");
+        let contents = "[laurn]\nmode = \"rust\"\n\n  netwrok = \"exposed\"\n";
+        match parse_value(path, contents) {
+            Err(ConfigError::Invalid(invalid)) => {
+                assert_eq!((invalid.line, invalid.column), (4, 3));
+                assert_eq!(invalid.suggestion, Some("network".to_string()));
+                assert_eq!(
+                    invalid.to_string().lines().next(),
+                    Some(".laurnrc:4:3: unknown field `netwrok`, expected one of `mode`, `network`, `conflict`, `hide`, `project`, `writable`, `protect`, `unprotect`, `volumes`, `inherit`, `workspace` for key `laurn` (did you mean `network`?)")
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let contents = "[laurn]\nnetwork = \"exposd\"\n";
+        match parse_value(path, contents) {
+            Err(ConfigError::Invalid(invalid)) => {
+                assert_eq!((invalid.line, invalid.column), (2, 1));
+                assert_eq!(invalid.suggestion, Some("exposed".to_string()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        let contents = "[limts]\npids = 12\n";
+        match parse_value(path, contents) {
+            Err(ConfigError::Invalid(invalid)) => {
+                assert_eq!(invalid.line, 1);
+                assert_eq!(invalid.suggestion, Some("limits".to_string()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // The same name in another table
+        let contents = "[tmp]\nsize = \"1g\"\n\n[limits]\nsize = 1\n";
+        match parse_value(path, contents) {
+            Err(ConfigError::Invalid(invalid)) => assert_eq!(invalid.line, 5),
+            other => panic!("unexpected {:?}", other),
+        }
+
+        // Nothing close enough
+        let contents = "[laurn]\nfoo = 1\n";
+        match parse_value(path, contents) {
+            Err(ConfigError::Invalid(invalid)) => assert_eq!(invalid.suggestion, None),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn version() {
+        let path = Path::new(".laurnrc");
+        assert!(parse_value(path, "version = 1\n[laurn]\nmode = \"rust\"\n").is_ok());
+        // Only the version is reported, not the keys this laurn does not know
+        match parse_value(path, "version = 2\n[laurn]\nsandbox = \"vm\"\n") {
+            Err(e @ ConfigError::UnsupportedVersion(_, 2)) => assert_eq!(
+                e.to_string(),
+                ".laurnrc: version 2 of the configuration requires a newer laurn (this one understands 1)"
+            ),
+            other => panic!("unexpected {:?}", other),
+        }
+        // mode is optional, like everything else
+        let config = load_config_str("[laurn]\nnetwork = \"exposed\"\n").expect("config");
+        assert_eq!(config.laurn.mode, vec![Mode::None]);
+    }
+
+    #[test]
+    fn origins() {
+        let dir = TempBuilder::new().tempdir().expect("tempdir");
+        let root = dir.path().join("repo");
+        let web = root.join("web");
+        fs::create_dir_all(&web).unwrap();
+        fs::write(
+            root.join(CONFIG_FILE),
+            "[laurn]\nmode = \"rust\"\nhide = [\"**/.env\"]\n",
+        )
+        .unwrap();
+        fs::write(
+            web.join(CONFIG_FILE),
+            "[laurn]\ninherit = true\nmode = \"node\"\n",
+        )
+        .unwrap();
+        let defaults: toml::Value = toml::from_str("[limits]\npids = 512\n").unwrap();
+
+        let mut project = load_project_in(&web, None, Some(&defaults)).expect("project");
+        let origin = |project: &Project, key: &str| project.origins.get(key).cloned();
+        let root_file = root.join(CONFIG_FILE).to_string_lossy().into_owned();
+        let web_file = web.join(CONFIG_FILE).to_string_lossy().into_owned();
+
+        assert_eq!(origin(&project, "laurn.mode"), Some(web_file.clone()));
+        assert_eq!(origin(&project, "laurn.hide"), Some(root_file));
+        assert_eq!(
+            origin(&project, "limits.pids"),
+            Some("user defaults".to_string())
+        );
+        assert_eq!(
+            origin(&project, "laurn.network"),
+            Some("default".to_string())
+        );
+
+        project.amend("command line", |config| {
+            config.laurn.network = NetworkConfig::Exposed;
+            config.limits.pids = None;
+        });
+        assert_eq!(
+            origin(&project, "laurn.network"),
+            Some("command line".to_string())
+        );
+        assert_eq!(origin(&project, "limits.pids"), None);
+
+        // What the policy overrides comes from it
+        let user: UserConfig =
+            toml::from_str("[defaults.limits]\npids = 512\n\n[policy]\nlimits = { pids = 128 }\n")
+                .unwrap();
+        let project = load_project(&web, &user).expect("project");
+        assert_eq!(project.config.limits.pids, Some(128));
+        assert_eq!(
+            origin(&project, "limits.pids"),
+            Some("user policy".to_string())
+        );
+        assert_eq!(origin(&project, "laurn.mode"), Some(web_file));
     }
 }
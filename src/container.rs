@@ -1,3 +1,5 @@
+use std::error::Error as StdError;
+use std::fmt;
 use std::io::Error as IoError;
 use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
@@ -19,6 +21,48 @@ pub enum BuildError {
     Build(BuildFailed),
 }
 
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Exec(ref e) => write!(f, "unable to run nix-store: {}", e),
+            Error::Code { exit_status } => write!(f, "nix-store exited with {}", exit_status),
+            Error::Truncated => write!(f, "the output of nix-store is truncated"),
+            Error::ParsingFailed => write!(f, "unable to parse the output of nix-store"),
+        }
+    }
+}
+
+impl StdError for Error {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            Error::Exec(ref e) => Some(e),
+            Error::Code { .. } | Error::Truncated | Error::ParsingFailed => None,
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BuildError::Source(ref e) => write!(f, "unable to find laurn.nix: {}", e),
+            BuildError::Instantiation(ref e) => {
+                write!(f, "unable to instantiate the environment: {}", e)
+            }
+            BuildError::Build(ref e) => write!(f, "unable to build the environment: {}", e),
+        }
+    }
+}
+
+impl StdError for BuildError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match *self {
+            BuildError::Source(ref e) => Some(e),
+            BuildError::Instantiation(ref e) => Some(e),
+            BuildError::Build(ref e) => Some(e),
+        }
+    }
+}
+
 pub struct Container {
     pub(crate) laurn_expr: PathBuf,
     pub(crate) output: Build,
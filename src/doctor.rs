@@ -0,0 +1,407 @@
+/// Check that the host provides what laurn needs, with hints to fix what it does not
+///
+/// Without it, a missing capability shows up as a bare `EPERM` from deep in the sandbox setup.
+use std::env;
+use std::fmt;
+use std::fs;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sched::{unshare, CloneFlags};
+use nix::sys::utsname::uname;
+use nix::sys::wait::{waitpid, WaitStatus};
+use nix::unistd::{self, access, AccessFlags, ForkResult};
+use nix::Error as SysError;
+
+use crate::cgroup::{delegation, CgroupError};
+use crate::landlock;
+
+/// Binaries laurn runs to build the environment
+const NIX_BINARIES: &[&str] = &["nix-instantiate", "nix-build", "nix-store"];
+const NIX_DAEMON_SOCKET: &str = "/nix/var/nix/daemon-socket/socket";
+/// Controllers used by `[limits]`
+const CONTROLLERS: &[&str] = &["memory", "pids", "cpu"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    /// laurn works, without some of its features
+    Warning,
+    /// laurn can not start a sandbox
+    Error,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: &'static str,
+    pub status: Status,
+    pub detail: String,
+    /// How to fix it
+    pub hint: Option<String>,
+}
+
+impl Check {
+    fn ok(name: &'static str, detail: impl Into<String>) -> Self {
+        Check {
+            name,
+            status: Status::Ok,
+            detail: detail.into(),
+            hint: None,
+        }
+    }
+
+    fn failed(
+        name: &'static str,
+        status: Status,
+        detail: impl Into<String>,
+        hint: impl Into<String>,
+    ) -> Self {
+        Check {
+            name,
+            status,
+            detail: detail.into(),
+            hint: Some(hint.into()),
+        }
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let status = match self.status {
+            Status::Ok => "ok",
+            Status::Warning => "warning",
+            Status::Error => "error",
+        };
+        write!(f, "{:<7} {}: {}", status, self.name, self.detail)?;
+        if let Some(ref hint) = self.hint {
+            write!(f, "\n        hint: {}", hint)?;
+        }
+        Ok(())
+    }
+}
+
+/// Run every check
+pub fn checks() -> Vec<Check> {
+    let release = uname().release().to_string();
+    vec![
+        user_namespaces(),
+        nix_binaries(),
+        nix_store(),
+        overlayfs(&release),
+        landlock(),
+        seccomp(),
+        cgroup(),
+    ]
+}
+
+fn sysctl(name: &str) -> Option<String> {
+    let path = Path::new("/proc/sys").join(name.replace('.', "/"));
+    fs::read_to_string(path).ok().map(|v| v.trim().to_string())
+}
+
+fn user_namespaces() -> Check {
+    let name = "user namespaces";
+    let status = match try_unshare() {
+        Ok(()) => return Check::ok(name, "unprivileged user namespaces can be created"),
+        Err(errno) => errno,
+    };
+    let (detail, hint) = userns_hint(
+        status,
+        sysctl("kernel.unprivileged_userns_clone").as_deref(),
+        sysctl("user.max_user_namespaces").as_deref(),
+        sysctl("kernel.apparmor_restrict_unprivileged_userns").as_deref(),
+    );
+    Check::failed(name, Status::Error, detail, hint)
+}
+
+/// Explain why a user namespace could not be created, from the sysctls known to restrict them
+fn userns_hint(
+    errno: Errno,
+    unprivileged_clone: Option<&str>,
+    max: Option<&str>,
+    apparmor: Option<&str>,
+) -> (String, String) {
+    let detail = format!("unable to create a user namespace ({})", errno.desc());
+    let hint = if unprivileged_clone == Some("0") {
+        "they are disabled by `kernel.unprivileged_userns_clone`, run `sysctl -w kernel.unprivileged_userns_clone=1`"
+    } else if max == Some("0") {
+        "they are disabled by `user.max_user_namespaces`, run `sysctl -w user.max_user_namespaces=15000`"
+    } else if apparmor == Some("1") {
+        "AppArmor restricts them (Ubuntu 23.10 and later), add an AppArmor profile for laurn with `userns,` or run `sysctl -w kernel.apparmor_restrict_unprivileged_userns=0`"
+    } else {
+        "when running in a container, its seccomp profile may block `unshare`"
+    };
+    (detail, hint.to_string())
+}
+
+/// Create a user and mount namespace in a child process, which leaves ours untouched
+fn try_unshare() -> Result<(), Errno> {
+    match unistd::fork() {
+        Ok(ForkResult::Child) => {
+            let code = match unshare(CloneFlags::CLONE_NEWUSER | CloneFlags::CLONE_NEWNS) {
+                Ok(()) => 0,
+                Err(SysError::Sys(errno)) => errno as i32,
+                Err(_) => libc::EINVAL,
+            };
+            unsafe { libc::_exit(code) }
+        }
+        Ok(ForkResult::Parent { child, .. }) => match waitpid(child, None) {
+            Ok(WaitStatus::Exited(_, 0)) => Ok(()),
+            Ok(WaitStatus::Exited(_, code)) => Err(Errno::from_i32(code)),
+            _ => Err(Errno::UnknownErrno),
+        },
+        Err(SysError::Sys(errno)) => Err(errno),
+        Err(_) => Err(Errno::UnknownErrno),
+    }
+}
+
+fn find_in_path(binary: &str) -> Option<PathBuf> {
+    env::var_os("PATH").and_then(|path| {
+        env::split_paths(&path)
+            .map(|dir| dir.join(binary))
+            .find(|path| path.is_file())
+    })
+}
+
+fn nix_binaries() -> Check {
+    let name = "nix";
+    let missing: Vec<&str> = NIX_BINARIES
+        .iter()
+        .filter(|binary| find_in_path(binary).is_none())
+        .cloned()
+        .collect();
+    if missing.is_empty() {
+        Check::ok(
+            name,
+            "nix-instantiate, nix-build and nix-store are in the PATH",
+        )
+    } else {
+        Check::failed(
+            name,
+            Status::Error,
+            format!("{} not found in the PATH", missing.join(", ")),
+            "install nix (https://nixos.org/download) and source its profile",
+        )
+    }
+}
+
+fn nix_store() -> Check {
+    let name = "nix store";
+    if Path::new(NIX_DAEMON_SOCKET).exists() {
+        match UnixStream::connect(NIX_DAEMON_SOCKET) {
+            Ok(_) => Check::ok(name, "the nix daemon is running"),
+            Err(e) => Check::failed(
+                name,
+                Status::Error,
+                format!("unable to connect to the nix daemon ({})", e),
+                "start it with `systemctl start nix-daemon`",
+            ),
+        }
+    } else if access("/nix/store", AccessFlags::W_OK).is_ok() {
+        Check::ok(name, "single-user installation, the store is writable")
+    } else {
+        Check::failed(
+            name,
+            Status::Error,
+            "no nix daemon and the store is not writable",
+            "start the nix daemon (`systemctl start nix-daemon`), or make /nix owned by the user for a single-user installation",
+        )
+    }
+}
+
+/// Major and minor version of a kernel release, `5.15.0-91-generic`
+fn kernel_version(release: &str) -> Option<(u32, u32)> {
+    let mut parts = release.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn overlayfs(release: &str) -> Check {
+    let name = "overlayfs";
+    let available = fs::read_to_string("/proc/filesystems")
+        .map(|fs| fs.lines().any(|line| line.ends_with("\toverlay")))
+        .unwrap_or(false);
+    match kernel_version(release) {
+        // Mounting overlayfs from a user namespace (with userxattr) needs linux 5.11
+        Some(version) if version < (5, 11) => Check::failed(
+            name,
+            Status::Warning,
+            format!(
+                "linux {} can not mount overlayfs in a user namespace",
+                release
+            ),
+            "--ephemeral requires linux 5.11 or later",
+        ),
+        // The module is loaded on first use
+        _ if !available => Check::ok(
+            name,
+            "not loaded yet, `modprobe overlay` if --ephemeral fails",
+        ),
+        _ => Check::ok(name, "available for --ephemeral"),
+    }
+}
+
+fn landlock() -> Check {
+    let name = "landlock";
+    match landlock::abi_version() {
+        Ok(abi) => Check::ok(name, format!("ABI version {}", abi)),
+        Err(SysError::Sys(Errno::EOPNOTSUPP)) => Check::failed(
+            name,
+            Status::Warning,
+            "disabled, the sandbox relies on mounts only",
+            "add landlock to the `lsm=` kernel command line",
+        ),
+        Err(_) => Check::failed(
+            name,
+            Status::Warning,
+            "not supported, the sandbox relies on mounts only",
+            "landlock requires linux 5.13 or later, built with CONFIG_SECURITY_LANDLOCK",
+        ),
+    }
+}
+
+fn seccomp() -> Check {
+    seccomp_state(&fs::read_to_string("/proc/self/status").unwrap_or_default())
+}
+
+/// Whether laurn itself runs under a seccomp filter or with no_new_privs, from
+/// `/proc/self/status`: laurn does not install any, but a container may
+fn seccomp_state(status: &str) -> Check {
+    let name = "seccomp";
+    let field = |field: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(field))
+            .map(str::trim)
+    };
+    let no_new_privs = match field("NoNewPrivs:") {
+        Some("1") => ", with no_new_privs set",
+        _ => "",
+    };
+    match field("Seccomp:") {
+        Some("0") => Check::ok(
+            name,
+            format!("laurn does not run under a seccomp filter{}", no_new_privs),
+        ),
+        Some(_) => Check::failed(
+            name,
+            Status::Warning,
+            format!(
+                "laurn runs under a seccomp filter{} (in a container?), it may refuse \
+                 `unshare` or `mount`",
+                no_new_privs
+            ),
+            "if the sandbox can not be set up, relax the seccomp profile of the container",
+        ),
+        None => Check::ok(
+            name,
+            format!("the kernel does not report a seccomp state{}", no_new_privs),
+        ),
+    }
+}
+
+fn cgroup() -> Check {
+    let name = "cgroup";
+    let hint_delegation =
+        "run laurn from a systemd user session, which delegates `user@$UID.service` to the user";
+    match delegation() {
+        Ok(delegation) => {
+            let missing: Vec<&str> = CONTROLLERS
+                .iter()
+                .filter(|c| !delegation.controllers.iter().any(|d| d == *c))
+                .cloned()
+                .collect();
+            if !missing.is_empty() {
+                return Check::failed(
+                    name,
+                    Status::Warning,
+                    format!(
+                        "{} not delegated, [limits] using them are refused",
+                        missing.join(", ")
+                    ),
+                    "add `Delegate=yes` to user@.service (systemctl edit user@.service)",
+                );
+            }
+
+            // Enabling a controller fails once processes live in the cgroup itself
+            for (path, enabled) in delegation.subtree_control.iter() {
+                let disabled: Vec<&str> = CONTROLLERS
+                    .iter()
+                    .filter(|c| !enabled.iter().any(|e| e == *c))
+                    .cloned()
+                    .collect();
+                if !disabled.is_empty() {
+                    return Check::failed(
+                        name,
+                        Status::Warning,
+                        format!(
+                            "{} not enabled in {}, [limits] using them fail if laurn can not \
+                             enable them",
+                            disabled.join(", "),
+                            path.display()
+                        ),
+                        format!(
+                            "echo '{}' > {}",
+                            disabled
+                                .iter()
+                                .map(|c| format!("+{}", c))
+                                .collect::<Vec<_>>()
+                                .join(" "),
+                            path.display()
+                        ),
+                    );
+                }
+            }
+            Check::ok(name, "memory, pids and cpu are delegated for [limits]")
+        }
+        Err(CgroupError::NotUnified) => Check::failed(
+            name,
+            Status::Warning,
+            "the host does not use cgroup v2, [limits] are unavailable",
+            "boot with `systemd.unified_cgroup_hierarchy=1`",
+        ),
+        Err(CgroupError::NoDelegation) => Check::failed(
+            name,
+            Status::Warning,
+            "no cgroup is delegated to the user, [limits] are unavailable",
+            hint_delegation,
+        ),
+        Err(e) => Check::failed(
+            name,
+            Status::Warning,
+            format!("unable to read the delegated cgroup ({})", e),
+            hint_delegation,
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hints() {
+        assert_eq!(kernel_version("5.15.0-91-generic"), Some((5, 15)));
+        assert_eq!(kernel_version("6.1-rc3"), Some((6, 1)));
+        assert_eq!(kernel_version("custom"), None);
+        assert_eq!(overlayfs("5.4.0-150-generic").status, Status::Warning);
+
+        let (_, hint) = userns_hint(Errno::EPERM, Some("0"), Some("15000"), None);
+        assert!(hint.contains("kernel.unprivileged_userns_clone=1"));
+        let (_, hint) = userns_hint(Errno::ENOSPC, None, Some("0"), None);
+        assert!(hint.contains("user.max_user_namespaces"));
+        let (detail, hint) = userns_hint(Errno::EACCES, Some("1"), Some("15000"), Some("1"));
+        assert!(detail.contains("Permission denied"));
+        assert!(hint.contains("AppArmor"));
+
+        let status = "Name:\tlaurn\nNoNewPrivs:\t0\nSeccomp:\t0\n";
+        assert_eq!(seccomp_state(status).status, Status::Ok);
+        let filtered = seccomp_state("NoNewPrivs:\t1\nSeccomp:\t2\n");
+        assert_eq!(filtered.status, Status::Warning);
+        assert!(filtered.detail.contains("no_new_privs"));
+        assert_eq!(seccomp_state("Name:\tlaurn\n").status, Status::Ok);
+    }
+}
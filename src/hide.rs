@@ -0,0 +1,210 @@
+/// Find the files of the project matching the `hide` patterns of the configuration
+///
+/// Patterns are relative to the project root:
+///  - `*` matches anything but `/`, `?` a single character
+///  - `**` matches any number of directories
+///  - a trailing `/` only matches directories
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+pub(crate) struct Pattern {
+    components: Vec<String>,
+    dir_only: bool,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Self {
+        let dir_only = pattern.ends_with('/');
+        let components = pattern
+            .split('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .map(String::from)
+            .collect();
+
+        Pattern {
+            components,
+            dir_only,
+        }
+    }
+
+    /// Whether the path (relative to the project root) matches
+    pub fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        let path: Vec<String> = path
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(c) => Some(c.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let pattern: Vec<&str> = self.components.iter().map(String::as_str).collect();
+        let path: Vec<&str> = path.iter().map(String::as_str).collect();
+
+        match_components(&pattern, &path)
+    }
+
+    /// Whether a path below the directory `dir` (relative to the project root) could match
+    pub fn matches_below(&self, dir: &Path) -> bool {
+        let dir: Vec<String> = dir
+            .components()
+            .filter_map(|c| match c {
+                Component::Normal(c) => Some(c.to_string_lossy().into_owned()),
+                _ => None,
+            })
+            .collect();
+        let pattern: Vec<&str> = self.components.iter().map(String::as_str).collect();
+        let dir: Vec<&str> = dir.iter().map(String::as_str).collect();
+
+        match_prefix(&pattern, &dir)
+    }
+}
+
+fn match_components(pattern: &[&str], path: &[&str]) -> bool {
+    match (pattern.split_first(), path.split_first()) {
+        (None, None) => true,
+        (Some((&"**", rest)), _) => {
+            match_components(rest, path)
+                || (!path.is_empty() && match_components(pattern, &path[1..]))
+        }
+        (Some((p, prest)), Some((c, crest))) => {
+            match_component(p.as_bytes(), c.as_bytes()) && match_components(prest, crest)
+        }
+        _ => false,
+    }
+}
+
+/// Whether the pattern can match a path starting with `prefix`, and longer than it
+fn match_prefix(pattern: &[&str], prefix: &[&str]) -> bool {
+    match (pattern.split_first(), prefix.split_first()) {
+        (Some((&"**", _)), _) => true,
+        (Some(_), None) => true,
+        (Some((p, prest)), Some((c, crest))) => {
+            match_component(p.as_bytes(), c.as_bytes()) && match_prefix(prest, crest)
+        }
+        (None, _) => false,
+    }
+}
+
+fn match_component(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, None) => true,
+        (Some((b'*', rest)), _) => {
+            match_component(rest, name)
+                || (!name.is_empty() && match_component(pattern, &name[1..]))
+        }
+        (Some((b'?', prest)), Some((_, nrest))) => match_component(prest, nrest),
+        (Some((p, prest)), Some((n, nrest))) => p == n && match_component(prest, nrest),
+        _ => false,
+    }
+}
+
+/// Walk the project and return the paths matching any of the patterns
+///
+/// Symlinks are neither followed nor hidden, and matched directories are not walked any further,
+/// nor are directories no pattern can match below (only patterns starting with `**` walk the whole
+/// project). Directories which can not be read are skipped.
+pub(crate) fn find(project_dir: &Path, patterns: &[String]) -> io::Result<Vec<PathBuf>> {
+    let patterns: Vec<Pattern> = patterns.iter().map(|p| Pattern::new(p)).collect();
+    let mut out = Vec::new();
+
+    if !patterns.is_empty() {
+        walk(project_dir, Path::new(""), &patterns, &mut out)?;
+    }
+
+    Ok(out)
+}
+
+fn walk(root: &Path, rel: &Path, patterns: &[Pattern], out: &mut Vec<PathBuf>) -> io::Result<()> {
+    let dir = root.join(rel);
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        // Nothing the user can not read can be leaked to the sandbox either
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            eprintln!(
+                "laurn: unable to look for files to hide in {}: {}",
+                dir.display(),
+                e
+            );
+            return Ok(());
+        }
+        Err(e) => return Err(e),
+    };
+
+    for entry in entries {
+        let entry = entry?;
+        let file_type = entry.file_type()?;
+        if file_type.is_symlink() {
+            continue;
+        }
+        let path = rel.join(entry.file_name());
+        let is_dir = file_type.is_dir();
+
+        if patterns.iter().any(|p| p.matches(&path, is_dir)) {
+            out.push(root.join(&path));
+        } else if is_dir && patterns.iter().any(|p| p.matches_below(&path)) {
+            walk(root, &path, patterns, out)?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matches(pattern: &str, path: &str, is_dir: bool) -> bool {
+        Pattern::new(pattern).matches(Path::new(path), is_dir)
+    }
+
+    #[test]
+    fn glob() {
+        assert!(matches("**/.env", ".env", false));
+        assert!(matches("**/.env", "services/api/.env", false));
+        assert!(!matches("**/.env", "services/api/.envrc", false));
+
+        assert!(matches("secrets/", "secrets", true));
+        assert!(!matches("secrets/", "secrets", false));
+        assert!(!matches("secrets/", "config/secrets", true));
+
+        assert!(matches("*.pem", "server.pem", false));
+        assert!(!matches("*.pem", "certs/server.pem", false));
+        assert!(matches("certs/*.pem", "certs/server.pem", false));
+        assert!(matches(
+            "**/terraform.tfstate",
+            "infra/terraform.tfstate",
+            false
+        ));
+        assert!(matches("key?.txt", "key1.txt", false));
+        assert!(!matches("key?.txt", "key10.txt", false));
+
+        let below = |pattern: &str, dir: &str| Pattern::new(pattern).matches_below(Path::new(dir));
+        assert!(below("certs/*.pem", "certs"));
+        assert!(!below("certs/*.pem", "node_modules"));
+        assert!(!below("certs/*.pem", "certs/old"));
+        assert!(!below("*.pem", "target"));
+        assert!(below("**/.env", "node_modules/a/b"));
+        assert!(below("services/**/.env", "services/api"));
+    }
+
+    #[test]
+    fn walk() {
+        let project = tempfile::Builder::new().tempdir().expect("tempdir");
+        let dir = project.path();
+        fs::create_dir_all(dir.join("certs")).unwrap();
+        fs::write(dir.join("certs/server.pem"), b"").unwrap();
+        fs::write(dir.join("secret.txt"), b"").unwrap();
+        // Hiding the link would hide its target instead
+        std::os::unix::fs::symlink("secret.txt", dir.join("link.pem")).unwrap();
+
+        let patterns = vec!["certs/*.pem".to_string(), "*.pem".to_string()];
+        assert_eq!(
+            find(dir, &patterns).unwrap(),
+            vec![dir.join("certs/server.pem")]
+        );
+    }
+}
@@ -0,0 +1,278 @@
+/// Scaffolding of a new project: a `laurn.nix` with the tools of the project and its `.laurnrc`
+///
+/// The type of the project is guessed from the files in its directory (`Cargo.toml`,
+/// `package.json`, ...). nixpkgs is pinned to the revision of the channel of the user, along with
+/// the hash of its source, so that the environment does not change under the project on the next
+/// channel update.
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use crate::config::{mode_name, Mode, CONFIG_FILE, CONFIG_VERSION, NIX_FILE};
+
+/// Files telling the type of a project
+const MARKERS: &[(&str, Mode)] = &[
+    ("rust-toolchain", Mode::Rustup),
+    ("rust-toolchain.toml", Mode::Rustup),
+    ("Cargo.toml", Mode::Rust),
+    ("package.json", Mode::Node),
+    ("pyproject.toml", Mode::Python),
+    ("requirements.txt", Mode::Python),
+    ("setup.py", Mode::Python),
+    ("go.mod", Mode::Go),
+    ("pom.xml", Mode::Jvm),
+    ("build.gradle", Mode::Jvm),
+    ("build.gradle.kts", Mode::Jvm),
+    ("stack.yaml", Mode::Haskell),
+    ("cabal.project", Mode::Haskell),
+];
+
+#[derive(Debug)]
+pub enum InitError {
+    /// The file exists, and overwriting it was not requested
+    Exists(PathBuf),
+    IO(PathBuf, io::Error),
+}
+
+impl fmt::Display for InitError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            InitError::Exists(ref path) => write!(
+                f,
+                "{} already exists, use --force to overwrite it",
+                path.display()
+            ),
+            InitError::IO(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl Error for InitError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            InitError::IO(_, ref e) => Some(e),
+            InitError::Exists(_) => None,
+        }
+    }
+}
+
+/// Modes of the project in `dir`, from the files it contains
+pub fn detect(dir: &Path) -> Vec<Mode> {
+    let mut out: Vec<Mode> = Vec::new();
+    for (marker, mode) in MARKERS.iter() {
+        if dir.join(marker).exists() && !out.contains(mode) {
+            out.push(*mode);
+        }
+    }
+    // A pinned toolchain is installed by rustup
+    if out.contains(&Mode::Rustup) {
+        out.retain(|mode| *mode != Mode::Rust);
+    }
+    out
+}
+
+/// Attributes of nixpkgs in the environment of each mode
+fn packages(mode: Mode) -> &'static [&'static str] {
+    match mode {
+        Mode::None => &[],
+        // Linking needs a C compiler
+        Mode::Rust => &["cargo", "rustc", "rustfmt", "clippy", "gcc"],
+        Mode::Rustup => &["rustup", "gcc"],
+        Mode::Node => &["nodejs"],
+        Mode::Python => &["python3", "python3Packages.pip"],
+        Mode::Go => &["go"],
+        Mode::Jvm => &["jdk", "maven", "gradle"],
+        Mode::Haskell => &["ghc", "cabal-install"],
+    }
+}
+
+/// Revision of the nixpkgs of the user (`<nixpkgs>`), when nix knows it
+fn nixpkgs_revision() -> Option<String> {
+    let output = Command::new("nix-instantiate")
+        .arg("--eval")
+        .arg("--expr")
+        .arg("(import <nixpkgs/lib>).trivial.revisionWithDefault \"\"")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let revision = String::from_utf8(output.stdout).ok()?;
+    let revision = revision.trim().trim_matches('"');
+    if revision.len() == 40 && revision.chars().all(|c| c.is_ascii_hexdigit()) {
+        Some(revision.to_string())
+    } else {
+        None
+    }
+}
+
+/// Source of nixpkgs the environment is pinned to
+struct Pin {
+    url: String,
+    /// Hash of the unpacked source, as `fetchTarball` checks it
+    sha256: String,
+}
+
+fn tarball_url(revision: &str) -> String {
+    format!(
+        "https://github.com/NixOS/nixpkgs/archive/{}.tar.gz",
+        revision
+    )
+}
+
+/// The nixpkgs of the user, when nix knows its revision and can fetch it
+fn nixpkgs_pin() -> Option<Pin> {
+    let url = tarball_url(&nixpkgs_revision()?);
+    let output = Command::new("nix-prefetch-url")
+        .arg("--unpack")
+        .arg(&url)
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let sha256 = String::from_utf8(output.stdout).ok()?;
+    let sha256 = sha256.trim();
+    if !sha256.is_empty() && sha256.chars().all(|c| c.is_ascii_alphanumeric()) {
+        Some(Pin {
+            url,
+            sha256: sha256.to_string(),
+        })
+    } else {
+        None
+    }
+}
+
+fn laurn_nix(name: &str, modes: &[Mode], pin: Option<&Pin>) -> String {
+    let nixpkgs = match pin {
+        Some(pin) => format!(
+            "  # nixpkgs pinned to the revision of the channel `laurn init` found\n  pkgs = import (builtins.fetchTarball {{\n    url = \"{}\";\n    sha256 = \"{}\";\n  }}) {{ }};\n",
+            pin.url, pin.sha256
+        ),
+        None => "  # The channel of the user, pin it with builtins.fetchTarball for reproducible environments\n  pkgs = import <nixpkgs> { };\n".to_string(),
+    };
+
+    let mut inputs: Vec<&str> = Vec::new();
+    for package in modes.iter().flat_map(|mode| packages(*mode).iter()) {
+        if !inputs.contains(package) {
+            inputs.push(package);
+        }
+    }
+    let inputs: String = inputs
+        .iter()
+        .map(|package| format!("    pkgs.{}\n", package))
+        .collect();
+
+    format!(
+        "# Environment of `laurn shell`, generated by `laurn init`\nlet\n{}in pkgs.mkShell {{\n  name = \"{}\";\n  buildInputs = [\n{}  ];\n}}\n",
+        nixpkgs,
+        name.replace(['"', '\\', '$'], "-"),
+        inputs
+    )
+}
+
+fn laurnrc(modes: &[Mode]) -> String {
+    let modes: Vec<String> = modes
+        .iter()
+        .map(|mode| format!("\"{}\"", mode_name(*mode)))
+        .collect();
+    let mode = match modes.len() {
+        0 => "\"none\"".to_string(),
+        1 => modes[0].clone(),
+        _ => format!("[{}]", modes.join(", ")),
+    };
+    format!("version = {}\n\n[laurn]\nmode = {}\n", CONFIG_VERSION, mode)
+}
+
+/// Write `laurn.nix` and `.laurnrc` in `dir`, returns the paths written
+///
+/// Nothing is written if one of them exists, unless `force` is set.
+pub fn init(dir: &Path, modes: &[Mode], force: bool) -> Result<Vec<PathBuf>, InitError> {
+    write(dir, modes, force, nixpkgs_pin().as_ref())
+}
+
+fn write(
+    dir: &Path,
+    modes: &[Mode],
+    force: bool,
+    pin: Option<&Pin>,
+) -> Result<Vec<PathBuf>, InitError> {
+    let nix_path = dir.join(NIX_FILE);
+    let config_path = dir.join(CONFIG_FILE);
+    if !force {
+        for path in [&nix_path, &config_path].iter() {
+            if path.exists() {
+                return Err(InitError::Exists(path.to_path_buf()));
+            }
+        }
+    }
+
+    let name = dir
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "laurn".to_string());
+    let files = [
+        (nix_path, laurn_nix(&name, modes, pin)),
+        (config_path, laurnrc(modes)),
+    ];
+    let mut out = Vec::with_capacity(files.len());
+    for (path, contents) in files.iter() {
+        fs::write(path, contents.as_bytes()).map_err(|e| InitError::IO(path.clone(), e))?;
+        out.push(path.clone());
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use tempfile::Builder as TempBuilder;
+
+    #[test]
+    fn scaffold() {
+        let dir = TempBuilder::new().tempdir().expect("tempdir");
+        let dir = dir.path();
+        assert_eq!(detect(dir), vec![]);
+        fs::write(dir.join("Cargo.toml"), b"[package]\n").unwrap();
+        fs::write(dir.join("package.json"), b"{}").unwrap();
+        assert_eq!(detect(dir), vec![Mode::Rust, Mode::Node]);
+        fs::write(dir.join("rust-toolchain.toml"), b"").unwrap();
+        assert_eq!(detect(dir), vec![Mode::Rustup, Mode::Node]);
+
+        let revision = "0123456789abcdef0123456789abcdef01234567";
+        let sha256 = "1x1k2lg41ck7sbr4w3nlyz1dzp8rl6rxqd1v4c0cs8x9vgz86bb3";
+        let modes = [Mode::Rust, Mode::Rustup];
+        let pin = Pin {
+            url: tarball_url(revision),
+            sha256: sha256.to_string(),
+        };
+        write(dir, &modes, false, Some(&pin)).unwrap();
+        let nix = fs::read_to_string(dir.join(NIX_FILE)).unwrap();
+        assert!(nix.contains(&format!(
+            "url = \"https://github.com/NixOS/nixpkgs/archive/{}.tar.gz\";\n    sha256 = \"{}\";",
+            revision, sha256
+        )));
+        // gcc is only listed once
+        assert_eq!(nix.matches("pkgs.gcc\n").count(), 1);
+        let config: Config =
+            toml::from_str(&fs::read_to_string(dir.join(CONFIG_FILE)).unwrap()).unwrap();
+        assert_eq!(config.laurn.mode, modes.to_vec());
+
+        match write(dir, &[Mode::Go], false, None) {
+            Err(InitError::Exists(path)) => assert_eq!(path, dir.join(NIX_FILE)),
+            other => panic!("unexpected {:?}", other),
+        }
+        write(dir, &[Mode::Go], true, None).unwrap();
+        let nix = fs::read_to_string(dir.join(NIX_FILE)).unwrap();
+        assert!(nix.contains("import <nixpkgs> { }") && nix.contains("pkgs.go\n"));
+    }
+}
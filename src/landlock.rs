@@ -0,0 +1,208 @@
+/// Landlock filesystem restrictions
+///
+/// Bind mounts are the primary isolation mechanism, landlock is applied on top of them just before
+/// exec so that a mistake in the mount setup does not give write access to the rest of the
+/// filesystem.
+///
+/// The libc we build against does not know about landlock yet, syscalls are issued by hand.
+use std::os::unix::io::RawFd;
+use std::path::{Path, PathBuf};
+
+use nix::errno::Errno;
+use nix::fcntl::{open, OFlag};
+use nix::libc;
+use nix::sys::stat::Mode;
+use nix::unistd::close;
+use nix::Error as SysError;
+
+// Syscall numbers are shared by all architectures (unified syscall table)
+const SYS_LANDLOCK_CREATE_RULESET: libc::c_long = 444;
+const SYS_LANDLOCK_ADD_RULE: libc::c_long = 445;
+const SYS_LANDLOCK_RESTRICT_SELF: libc::c_long = 446;
+
+const LANDLOCK_CREATE_RULESET_VERSION: u32 = 1 << 0;
+const LANDLOCK_RULE_PATH_BENEATH: libc::c_int = 1;
+
+const ACCESS_FS_EXECUTE: u64 = 1 << 0;
+const ACCESS_FS_WRITE_FILE: u64 = 1 << 1;
+const ACCESS_FS_READ_FILE: u64 = 1 << 2;
+const ACCESS_FS_READ_DIR: u64 = 1 << 3;
+const ACCESS_FS_REMOVE_DIR: u64 = 1 << 4;
+const ACCESS_FS_REMOVE_FILE: u64 = 1 << 5;
+const ACCESS_FS_MAKE_CHAR: u64 = 1 << 6;
+const ACCESS_FS_MAKE_DIR: u64 = 1 << 7;
+const ACCESS_FS_MAKE_REG: u64 = 1 << 8;
+const ACCESS_FS_MAKE_SOCK: u64 = 1 << 9;
+const ACCESS_FS_MAKE_FIFO: u64 = 1 << 10;
+const ACCESS_FS_MAKE_BLOCK: u64 = 1 << 11;
+const ACCESS_FS_MAKE_SYM: u64 = 1 << 12;
+// ABI v2
+const ACCESS_FS_REFER: u64 = 1 << 13;
+// ABI v3
+const ACCESS_FS_TRUNCATE: u64 = 1 << 14;
+
+const ACCESS_FS_READ: u64 = ACCESS_FS_EXECUTE | ACCESS_FS_READ_FILE | ACCESS_FS_READ_DIR;
+
+#[repr(C)]
+struct RulesetAttr {
+    handled_access_fs: u64,
+}
+
+#[repr(C, packed)]
+struct PathBeneathAttr {
+    allowed_access: u64,
+    parent_fd: i32,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Status {
+    /// The ruleset is enforced, with the given landlock ABI version
+    Enforced(i32),
+    /// The kernel does not support landlock (or it is disabled)
+    Unsupported,
+}
+
+/// Paths the sandboxed processes are allowed to access, all others will be denied.
+///
+/// Paths are expressed as seen from inside the sandbox (after chroot).
+#[derive(Debug, Default)]
+pub(crate) struct Ruleset {
+    pub ro: Vec<PathBuf>,
+    pub rw: Vec<PathBuf>,
+}
+
+impl Ruleset {
+    /// Restrict the current process (and its future children) to the ruleset.
+    pub fn restrict_self(&self) -> Result<Status, SysError> {
+        let abi = match abi_version() {
+            Ok(abi) => abi,
+            Err(SysError::Sys(Errno::ENOSYS)) | Err(SysError::Sys(Errno::EOPNOTSUPP)) => {
+                return Ok(Status::Unsupported)
+            }
+            Err(e) => return Err(e),
+        };
+
+        let handled = handled_access(abi);
+        let attr = RulesetAttr {
+            handled_access_fs: handled,
+        };
+        let ruleset = syscall_result(unsafe {
+            libc::syscall(
+                SYS_LANDLOCK_CREATE_RULESET,
+                &attr as *const RulesetAttr,
+                std::mem::size_of::<RulesetAttr>(),
+                0,
+            )
+        })? as RawFd;
+
+        let res = self.add_rules(ruleset, handled).and_then(|_| {
+            syscall_result(unsafe { libc::prctl(libc::PR_SET_NO_NEW_PRIVS, 1, 0, 0, 0) }.into())?;
+            syscall_result(unsafe { libc::syscall(SYS_LANDLOCK_RESTRICT_SELF, ruleset, 0) })
+        });
+        let _ = close(ruleset);
+
+        res.map(|_| Status::Enforced(abi))
+    }
+
+    fn add_rules(&self, ruleset: RawFd, handled: u64) -> Result<(), SysError> {
+        for path in self.ro.iter() {
+            add_rule(ruleset, path, ACCESS_FS_READ & handled)?;
+        }
+        for path in self.rw.iter() {
+            add_rule(ruleset, path, handled)?;
+        }
+        Ok(())
+    }
+}
+
+/// Landlock ABI version supported by the kernel
+pub(crate) fn abi_version() -> Result<i32, SysError> {
+    let null: *const RulesetAttr = std::ptr::null();
+    syscall_result(unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_CREATE_RULESET,
+            null,
+            0,
+            LANDLOCK_CREATE_RULESET_VERSION,
+        )
+    })
+    .map(|v| v as i32)
+}
+
+fn handled_access(abi: i32) -> u64 {
+    let mut access = ACCESS_FS_EXECUTE
+        | ACCESS_FS_WRITE_FILE
+        | ACCESS_FS_READ_FILE
+        | ACCESS_FS_READ_DIR
+        | ACCESS_FS_REMOVE_DIR
+        | ACCESS_FS_REMOVE_FILE
+        | ACCESS_FS_MAKE_CHAR
+        | ACCESS_FS_MAKE_DIR
+        | ACCESS_FS_MAKE_REG
+        | ACCESS_FS_MAKE_SOCK
+        | ACCESS_FS_MAKE_FIFO
+        | ACCESS_FS_MAKE_BLOCK
+        | ACCESS_FS_MAKE_SYM;
+    if abi >= 2 {
+        access |= ACCESS_FS_REFER;
+    }
+    if abi >= 3 {
+        access |= ACCESS_FS_TRUNCATE;
+    }
+    access
+}
+
+fn add_rule(ruleset: RawFd, path: &Path, access: u64) -> Result<(), SysError> {
+    let fd = match open(path, OFlag::O_PATH | OFlag::O_CLOEXEC, Mode::empty()) {
+        Ok(fd) => fd,
+        // Paths that were not exposed (not existing on the host) are skipped
+        Err(SysError::Sys(Errno::ENOENT)) => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    // Only directories may carry directory-related rights
+    let access = if path.is_dir() {
+        access
+    } else {
+        access
+            & (ACCESS_FS_EXECUTE | ACCESS_FS_WRITE_FILE | ACCESS_FS_READ_FILE | ACCESS_FS_TRUNCATE)
+    };
+
+    let attr = PathBeneathAttr {
+        allowed_access: access,
+        parent_fd: fd,
+    };
+    let res = syscall_result(unsafe {
+        libc::syscall(
+            SYS_LANDLOCK_ADD_RULE,
+            ruleset,
+            LANDLOCK_RULE_PATH_BENEATH,
+            &attr as *const PathBeneathAttr,
+            0,
+        )
+    });
+    let _ = close(fd);
+
+    res.map(|_| ())
+}
+
+fn syscall_result(ret: libc::c_long) -> Result<libc::c_long, SysError> {
+    if ret < 0 {
+        Err(SysError::last())
+    } else {
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handled_access_per_abi() {
+        assert_eq!(handled_access(1) & ACCESS_FS_REFER, 0);
+        assert_eq!(handled_access(2) & ACCESS_FS_REFER, ACCESS_FS_REFER);
+        assert_eq!(handled_access(2) & ACCESS_FS_TRUNCATE, 0);
+        assert_eq!(handled_access(3) & ACCESS_FS_TRUNCATE, ACCESS_FS_TRUNCATE);
+    }
+}
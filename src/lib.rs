@@ -1,11 +1,24 @@
 mod build;
+mod cgroup;
 mod container;
 
 pub use build::{BuildFailed, InstantiationFailed};
+pub use cgroup::CgroupError;
 pub use container::{BuildError, Container};
+pub use strategy::StrategyError;
 
 pub mod config;
+pub mod doctor;
+pub mod init;
+pub mod overlay;
+pub mod plan;
+pub mod render;
 pub mod run;
+pub mod trust;
+pub mod user;
+pub mod volumes;
 
+mod hide;
+mod landlock;
 mod strategy;
 mod utils;
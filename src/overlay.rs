@@ -0,0 +1,170 @@
+/// Ephemeral sessions: the project is mounted with an overlayfs, writes end up in an upper
+/// directory which is thrown away (or kept for inspection) at the end of the session.
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::fs::{FileTypeExt, MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+
+/// Options of an ephemeral session
+#[derive(Debug, Default, Clone)]
+pub struct Ephemeral {
+    /// Where to save the changes made during the session
+    pub keep_changes: Option<PathBuf>,
+}
+
+pub(crate) struct Overlay {
+    pub upper: PathBuf,
+    pub work: PathBuf,
+}
+
+impl Overlay {
+    /// Prepare the upper and work directories, in the working directory of laurn unless the
+    /// changes are to be kept.
+    pub fn create(working_dir: &Path, ephemeral: &Ephemeral) -> io::Result<Self> {
+        let base = match ephemeral.keep_changes {
+            Some(ref dir) => {
+                // Refuse to mix the changes with the content of an existing directory
+                match fs::read_dir(dir) {
+                    Ok(mut entries) => {
+                        if entries.next().is_some() {
+                            return Err(io::Error::new(
+                                ErrorKind::AlreadyExists,
+                                format!("{} is not empty", dir.display()),
+                            ));
+                        }
+                    }
+                    Err(e) if e.kind() == ErrorKind::NotFound => fs::create_dir_all(dir)?,
+                    Err(e) => return Err(e),
+                }
+                dir.clone()
+            }
+            None => working_dir.to_path_buf(),
+        };
+
+        let overlay = Overlay::at(&base);
+        fs::create_dir(&overlay.upper)?;
+        fs::create_dir(&overlay.work)?;
+
+        Ok(overlay)
+    }
+
+    /// The upper and work directories below `base`, without creating them
+    pub fn at(base: &Path) -> Self {
+        // upperdir and workdir need to be on the same filesystem
+        Overlay {
+            upper: base.join("upper"),
+            work: base.join("work"),
+        }
+    }
+
+    /// Options of the overlay mount
+    pub fn mount_data(&self, lower: &Path) -> String {
+        // userxattr is required to mount overlayfs from a user namespace
+        format!(
+            "lowerdir={},upperdir={},workdir={},userxattr",
+            lower.display(),
+            self.upper.display(),
+            self.work.display()
+        )
+    }
+
+    /// The work directory is only meaningful to a mounted overlay
+    pub fn cleanup(&self) {
+        // overlayfs leaves a directory without any permission behind
+        let _ = fs::set_permissions(self.work.join("work"), fs::Permissions::from_mode(0o700));
+        let _ = fs::remove_dir_all(&self.work);
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Change {
+    Added(PathBuf),
+    Modified(PathBuf),
+    Deleted(PathBuf),
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Change::Added(ref p) => write!(f, "A {}", p.display()),
+            Change::Modified(ref p) => write!(f, "M {}", p.display()),
+            Change::Deleted(ref p) => write!(f, "D {}", p.display()),
+        }
+    }
+}
+
+/// Summarize the changes recorded in the upper directory of an overlay
+pub fn diff(upper: &Path, lower: &Path) -> io::Result<Vec<Change>> {
+    let mut out = Vec::new();
+    walk(upper, lower, Path::new(""), &mut out)?;
+    Ok(out)
+}
+
+fn walk(upper: &Path, lower: &Path, rel: &Path, out: &mut Vec<Change>) -> io::Result<()> {
+    let mut entries = fs::read_dir(upper.join(rel))?.collect::<io::Result<Vec<_>>>()?;
+    entries.sort_by_key(|e| e.file_name());
+
+    for entry in entries {
+        let path = rel.join(entry.file_name());
+        let metadata = entry.metadata()?;
+        let file_type = metadata.file_type();
+        let in_lower = fs::symlink_metadata(lower.join(&path)).is_ok();
+
+        // overlayfs records deletions as 0:0 character devices (whiteouts)
+        if file_type.is_char_device() && metadata.rdev() == 0 {
+            out.push(Change::Deleted(path));
+        } else if !in_lower {
+            out.push(Change::Added(path));
+        } else if file_type.is_dir() {
+            walk(upper, lower, &path, out)?;
+        } else {
+            out.push(Change::Modified(path));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::Builder as TempBuilder;
+
+    #[test]
+    fn changes() {
+        let lower = TempBuilder::new().tempdir().expect("tempdir");
+        let upper = TempBuilder::new().tempdir().expect("tempdir");
+
+        fs::create_dir(lower.path().join("src")).unwrap();
+        fs::write(lower.path().join("src/main.rs"), b"fn main() {}").unwrap();
+        fs::write(lower.path().join("README"), b"").unwrap();
+
+        fs::create_dir(upper.path().join("src")).unwrap();
+        fs::write(upper.path().join("src/main.rs"), b"fn main() { evil() }").unwrap();
+        fs::create_dir(upper.path().join("node_modules")).unwrap();
+        fs::write(upper.path().join("node_modules/evil.js"), b"").unwrap();
+
+        let changes = diff(upper.path(), lower.path()).expect("diff");
+        assert_eq!(
+            changes,
+            vec![
+                Change::Added(PathBuf::from("node_modules")),
+                Change::Modified(PathBuf::from("src/main.rs")),
+            ]
+        );
+        assert_eq!(changes[0].to_string(), "A node_modules");
+    }
+
+    #[test]
+    fn keep_changes_refuses_non_empty() {
+        let working_dir = TempBuilder::new().tempdir().expect("tempdir");
+        let keep = TempBuilder::new().tempdir().expect("tempdir");
+        fs::write(keep.path().join("file"), b"").unwrap();
+
+        let ephemeral = Ephemeral {
+            keep_changes: Some(keep.path().to_path_buf()),
+        };
+        assert!(Overlay::create(working_dir.path(), &ephemeral).is_err());
+    }
+}
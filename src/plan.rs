@@ -0,0 +1,730 @@
+/// The mounts of a session, decided before anything is mounted
+///
+/// Building the plan has no side effect: `laurn inspect` prints it without creating any
+/// namespace, and `run` executes it as is. Targets are paths in the sandbox, the executor roots
+/// them in its working directory until it chroots.
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use home::home_dir;
+use serde_derive::Serialize;
+
+use crate::config::{Config, ProjectMode, SysMode, CONFIG_FILE, NIX_FILE};
+use crate::container::Container;
+use crate::hide;
+use crate::overlay::Overlay;
+use crate::run::{RunError, Workspace};
+use crate::strategy::{ExposedPath, Strategy};
+use crate::user::parse_bytes;
+use crate::utils::{data_dir, project_hash, PathMerge};
+use crate::volumes::{self, VolumeError};
+
+/// Devices bind-mounted from the host, we run in a user namespace and can not mknod
+const DEVICES: &[&str] = &[
+    "/dev/null",
+    "/dev/zero",
+    "/dev/full",
+    "/dev/random",
+    "/dev/urandom",
+    "/dev/tty",
+    "/dev/console",
+];
+
+/// Scratch space private to the session
+const TMP_DIRS: &[&str] = &["/tmp", "/var/tmp", "/run"];
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MountMode {
+    RW,
+    RO,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Kind {
+    /// Dependencies of the environment, from the nix store
+    Nix,
+    /// Files of the host system
+    Host,
+    Project,
+    /// Paths of the home directory exposed by the modes
+    Home,
+    /// Storage persisted by laurn (volumes, persistent `/tmp`)
+    Volume,
+    /// The project under an overlayfs, for ephemeral sessions
+    Overlay,
+    Dev,
+    Sys,
+    Tmpfs,
+    /// The socket of the ssh-agent
+    Socket,
+    Proc,
+    Devpts,
+    Shm,
+    /// Sensitive paths of `/proc` and `/sys`, and credentials refused by the modes
+    Mask,
+    /// Files of the project matching `hide`
+    Hidden,
+    /// Paths of `/proc` and `/sys` remounted read-only
+    ReadOnly,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match *self {
+            Kind::Nix => "nix",
+            Kind::Host => "host",
+            Kind::Project => "project",
+            Kind::Home => "home",
+            Kind::Volume => "volume",
+            Kind::Overlay => "overlay",
+            Kind::Dev => "dev",
+            Kind::Sys => "sys",
+            Kind::Tmpfs => "tmpfs",
+            Kind::Socket => "socket",
+            Kind::Proc => "proc",
+            Kind::Devpts => "devpts",
+            Kind::Shm => "shm",
+            Kind::Mask => "mask",
+            Kind::Hidden => "hidden",
+            Kind::ReadOnly => "read-only",
+        };
+        f.pad(name)
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Mount {
+    pub kind: Kind,
+    /// Path on the host, or the name of the filesystem
+    pub source: PathBuf,
+    /// Path in the sandbox
+    pub target: PathBuf,
+    pub mode: MountMode,
+    /// Options of the filesystem
+    pub data: Option<String>,
+}
+
+impl Mount {
+    fn bind(kind: Kind, source: &Path, target: &Path, mode: MountMode) -> Self {
+        Mount {
+            kind,
+            source: source.to_path_buf(),
+            target: target.to_path_buf(),
+            mode,
+            data: None,
+        }
+    }
+
+    fn fs(kind: Kind, fs: &str, target: &str, data: Option<String>) -> Self {
+        Mount {
+            kind,
+            source: PathBuf::from(fs),
+            target: PathBuf::from(target),
+            mode: MountMode::RW,
+            data,
+        }
+    }
+
+    fn mask(kind: Kind, target: &Path) -> Self {
+        Mount {
+            kind,
+            source: PathBuf::from(kind.to_string()),
+            target: target.to_path_buf(),
+            mode: MountMode::RO,
+            data: None,
+        }
+    }
+}
+
+/// Paths landlock allows, what is mounted is declared again as a second layer of defence
+///
+/// Landlock rules only add access: a read-only path beneath a writable one (`.git` in a writable
+/// project) would enforce nothing, it is left out and only its read-only mount protects it.
+#[derive(Serialize, Debug, Default, Clone, PartialEq, Eq)]
+pub struct Landlock {
+    pub ro: Vec<PathBuf>,
+    pub rw: Vec<PathBuf>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct Plan {
+    /// Directories created beforehand: the writable directories of the project need to exist,
+    /// as well as the persistent `/tmp`
+    pub create: Vec<PathBuf>,
+    /// Mounted in order, before the chroot
+    pub mounts: Vec<Mount>,
+    /// Mounted in order, from within the chroot
+    pub chrooted: Vec<Mount>,
+    pub landlock: Landlock,
+    /// Where the command starts
+    pub workdir: PathBuf,
+    /// Directories prepended to the PATH of the shell
+    pub bin_paths: Vec<PathBuf>,
+}
+
+/// Location of an exposed path on the host, which is also where it will be found in the sandbox
+pub(crate) fn exposed_source(path: &ExposedPath, project_dir: &Path) -> Result<PathBuf, RunError> {
+    match *path {
+        ExposedPath::Project(ref pp) => Ok(project_dir.merge(pp.as_path())),
+        ExposedPath::UserHome(ref up) => {
+            let home = home_dir().ok_or(RunError::GuessHomeDir)?;
+            Ok(home.as_path().merge(up.as_path()))
+        }
+    }
+}
+
+fn exposed_kind(path: &ExposedPath) -> Kind {
+    match *path {
+        ExposedPath::Project(_) => Kind::Project,
+        ExposedPath::UserHome(_) => Kind::Home,
+    }
+}
+
+/// Plan the session of the environment built in `container`
+pub(crate) fn plan(
+    container: &Container,
+    config: &Config,
+    workspace: Option<&Workspace>,
+    overlay: Option<&Overlay>,
+) -> Result<Plan, RunError> {
+    let nix_dir = container.laurn_expr.parent().ok_or(RunError::Mkdir)?;
+    let dependencies = container.references().map_err(RunError::Dependencies)?;
+    plan_with(&dependencies, nix_dir, config, workspace, overlay)
+}
+
+fn plan_with(
+    dependencies: &[PathBuf],
+    nix_dir: &Path,
+    config: &Config,
+    workspace: Option<&Workspace>,
+    overlay: Option<&Overlay>,
+) -> Result<Plan, RunError> {
+    let (project_dir, workdir) = match workspace {
+        Some(workspace) => (workspace.root.as_path(), workspace.workdir.as_path()),
+        None => (nix_dir, nix_dir),
+    };
+    // The configuration of a sub-project is as protected as the one of the workspace
+    let member = nix_dir
+        .strip_prefix(project_dir)
+        .unwrap_or_else(|_| Path::new(""));
+    let mut config_dirs = vec![member.to_path_buf()];
+    for dir in workspace.iter().flat_map(|w| w.config_dirs.iter()) {
+        if let Ok(dir) = dir.strip_prefix(project_dir) {
+            if !config_dirs.iter().any(|d| d == dir) {
+                config_dirs.push(dir.to_path_buf());
+            }
+        }
+    }
+    if !config_dirs.iter().any(|d| d.as_os_str().is_empty()) {
+        config_dirs.push(PathBuf::new());
+    }
+    // The other sub-projects of the workspace are as protected, or this one could poison their
+    // configuration for their next session
+    let mut members = vec![member.to_path_buf()];
+    if workspace.is_some() {
+        let patterns = [format!("**/{}", CONFIG_FILE), format!("**/{}", NIX_FILE)];
+        for path in hide::find(project_dir, &patterns).map_err(RunError::Members)? {
+            let dir = path
+                .parent()
+                .and_then(|dir| dir.strip_prefix(project_dir).ok())
+                .unwrap_or_else(|| Path::new(""));
+            if !members.iter().any(|m| m == dir) {
+                members.push(dir.to_path_buf());
+            }
+            if !config_dirs.iter().any(|d| d == dir) {
+                config_dirs.push(dir.to_path_buf());
+            }
+        }
+    }
+
+    let mut create = Vec::new();
+    let mut mounts = Vec::new();
+    let mut landlock = Landlock::default();
+
+    // First the nix dependencies (and the main shell "entrypoint"), read-only
+    for dep in dependencies.iter() {
+        mounts.push(Mount::bind(Kind::Nix, dep, dep, MountMode::RO));
+        landlock.ro.push(dep.clone());
+    }
+
+    // TODO(baloo): on github, resolv.conf can't be be remounted, mount it RW for now as it's out
+    // of reach anyway
+    let resolv = Path::new("/etc/resolv.conf");
+    mounts.push(Mount::bind(Kind::Host, resolv, resolv, MountMode::RW));
+    landlock.ro.push(resolv.to_path_buf());
+
+    // Depending on the configuration, we want to expose things from $HOME or project other things
+    // (the laurn config itself, git, ...)
+    let strategy = Strategy::from_modes(&config.laurn.mode, config.laurn.conflict)
+        .map(|s| members.iter().fold(s, |s, member| s.member(member)))
+        .and_then(|s| s.protect(&config.laurn.protect, &config.laurn.unprotect))
+        .and_then(|s| s.project(config.laurn.project, &config.laurn.writable))
+        .map_err(RunError::Strategy)?;
+
+    // Then the project itself
+    match (overlay, strategy.project) {
+        // Writes of an ephemeral session end up in the upper directory of the overlay
+        (Some(overlay), _) => {
+            mounts.push(Mount {
+                kind: Kind::Overlay,
+                source: PathBuf::from("overlay"),
+                target: project_dir.to_path_buf(),
+                mode: MountMode::RW,
+                data: Some(overlay.mount_data(project_dir)),
+            });
+            landlock.rw.push(project_dir.to_path_buf());
+        }
+        (None, ProjectMode::ReadWrite) => {
+            mounts.push(Mount::bind(
+                Kind::Project,
+                project_dir,
+                project_dir,
+                MountMode::RW,
+            ));
+            landlock.rw.push(project_dir.to_path_buf());
+        }
+        (None, ProjectMode::ReadOnly) => {
+            // The writable paths of the project are the only ones planned for
+            create.extend(strategy.rw_paths.iter().filter_map(|p| match *p {
+                ExposedPath::Project(ref p) => Some(project_dir.join(p)),
+                ExposedPath::UserHome(_) => None,
+            }));
+            mounts.push(Mount::bind(
+                Kind::Project,
+                project_dir,
+                project_dir,
+                MountMode::RO,
+            ));
+            landlock.ro.push(project_dir.to_path_buf());
+        }
+    }
+
+    // Volumes over a read-only project are mounted on directories created beforehand
+    let volumes = volumes::resolve(project_dir, &config.laurn.volumes).map_err(RunError::Volume)?;
+    if overlay.is_none() && strategy.project == ProjectMode::ReadOnly {
+        for volume in volumes.iter() {
+            if volume.target.starts_with(project_dir) && !create.contains(&volume.target) {
+                create.push(volume.target.clone());
+            }
+        }
+    }
+
+    // Paths which do not exist are not exposed
+    let exposed = |mounts: &mut Vec<Mount>, path: &ExposedPath, mode| {
+        let source = exposed_source(path, project_dir)?;
+        if source.exists() || create.contains(&source) {
+            mounts.push(Mount::bind(exposed_kind(path), &source, &source, mode));
+        }
+        Ok::<_, RunError>(source)
+    };
+
+    for ro_path in strategy.ro_paths.iter() {
+        landlock
+            .ro
+            .push(exposed(&mut mounts, ro_path, MountMode::RO)?);
+    }
+    for rw_path in strategy.rw_paths.iter() {
+        // Binding a directory of the project would let writes escape the overlay
+        if overlay.is_some() {
+            if let ExposedPath::Project(_) = rw_path {
+                continue;
+            }
+        }
+        landlock
+            .rw
+            .push(exposed(&mut mounts, rw_path, MountMode::RW)?);
+    }
+
+    // The protected paths and the configuration of laurn, mounted again after everything else
+    let mut protected: Vec<ExposedPath> = strategy
+        .protected_paths()
+        .map(|p| ExposedPath::Project(p.to_path_buf()))
+        .collect();
+    for config_path in Strategy::config_paths(&config_dirs) {
+        if !protected.contains(&config_path) {
+            protected.push(config_path);
+        }
+    }
+
+    // Volumes come after, they replace the caches shared with the host
+    for volume in volumes.iter() {
+        // Any directory of the project can be replaced, but the protected ones
+        let target = volume.target.as_path();
+        if project_dir.starts_with(target) {
+            return Err(RunError::Volume(VolumeError::Protected(
+                volume.name.clone(),
+                project_dir.to_path_buf(),
+            )));
+        }
+        for path in protected.iter() {
+            let path = exposed_source(path, project_dir)?;
+            if path.starts_with(target) || target.starts_with(&path) {
+                return Err(RunError::Volume(VolumeError::Protected(
+                    volume.name.clone(),
+                    path,
+                )));
+            }
+        }
+        mounts.push(Mount::bind(
+            Kind::Volume,
+            &volume.source,
+            &volume.target,
+            MountMode::RW,
+        ));
+        landlock.rw.push(volume.target.clone());
+    }
+
+    // Whatever got mounted over the project, its protected paths and the configuration of laurn
+    // stay read-only
+    for path in protected.iter() {
+        exposed(&mut mounts, path, MountMode::RO)?;
+    }
+
+    for dev in DEVICES.iter() {
+        let dev = Path::new(dev);
+        mounts.push(Mount::bind(Kind::Dev, dev, dev, MountMode::RW));
+    }
+
+    for dir in TMP_DIRS.iter() {
+        if *dir == "/tmp" && config.tmp.persist {
+            let source = data_dir()
+                .ok_or(RunError::GuessDataDir)?
+                .join("tmp")
+                .join(project_hash(project_dir));
+            create.push(source.clone());
+            mounts.push(Mount::bind(
+                Kind::Volume,
+                &source,
+                Path::new(dir),
+                MountMode::RW,
+            ));
+        } else {
+            // Written as a number, so that the size can not carry other mount options
+            let size = match parse_bytes(&config.tmp.size) {
+                Some(size) if size != u64::MAX => size,
+                _ => return Err(RunError::TmpSize(config.tmp.size.clone())),
+            };
+            let data = format!("size={},mode=1777", size);
+            mounts.push(Mount::fs(Kind::Tmpfs, "tmpfs", dir, Some(data)));
+        }
+        landlock.rw.push(PathBuf::from(dir));
+    }
+
+    // The socket of the agent usually lives in /tmp or /run, mount it over the tmpfs
+    if config.session.ssh_agent {
+        if let Some(socket) = std::env::var_os("SSH_AUTH_SOCK").map(PathBuf::from) {
+            if socket.exists() {
+                mounts.push(Mount::bind(Kind::Socket, &socket, &socket, MountMode::RW));
+                landlock.rw.push(socket);
+            }
+        }
+    }
+
+    // Only root can mount sysfs, we need to bindmount that
+    let sys = Path::new("/sys");
+    match config.masking.sys {
+        SysMode::ReadOnly => {
+            mounts.push(Mount::bind(Kind::Sys, sys, sys, MountMode::RO));
+            landlock.ro.push(sys.to_path_buf());
+        }
+        SysMode::ReadWrite => {
+            mounts.push(Mount::bind(Kind::Sys, sys, sys, MountMode::RW));
+            landlock.rw.push(sys.to_path_buf());
+        }
+        SysMode::Empty => {}
+    }
+    // The devices mounted are the only ones written to
+    landlock.ro.push(PathBuf::from("/proc"));
+    landlock.ro.push(PathBuf::from("/dev"));
+    landlock.rw.extend(DEVICES.iter().map(PathBuf::from));
+    for dev in ["/dev/pts", "/dev/ptmx", "/dev/shm"].iter() {
+        landlock.rw.push(PathBuf::from(dev));
+    }
+    let writable = landlock.rw.clone();
+    landlock
+        .ro
+        .retain(|ro| !writable.iter().any(|rw| ro.starts_with(rw)));
+
+    let mut chrooted = vec![Mount::fs(Kind::Proc, "proc", "/proc", None)];
+
+    // Hide the sensitive parts of /proc and /sys
+    for path in config.masking.masked() {
+        chrooted.push(Mount::mask(Kind::Mask, &path));
+    }
+    for path in config.masking.readonly() {
+        chrooted.push(Mount::mask(Kind::ReadOnly, &path));
+    }
+    // Credentials the strategy refuses to expose, only visible if a parent directory was exposed
+    for hidden_path in strategy.hidden_paths.iter() {
+        chrooted.push(Mount::mask(
+            Kind::Mask,
+            &exposed_source(hidden_path, project_dir)?,
+        ));
+    }
+    // Files of the project that should not be visible to dependencies
+    for path in hide::find(project_dir, &config.laurn.hide)
+        .map_err(RunError::Hide)?
+        .iter()
+    {
+        chrooted.push(Mount::mask(Kind::Hidden, path));
+    }
+
+    chrooted.push(Mount::fs(
+        Kind::Devpts,
+        "devpts",
+        "/dev/pts",
+        Some("mode=620,ptmxmode=666".to_string()),
+    ));
+    chrooted.push(Mount::fs(
+        Kind::Shm,
+        "tmpfs",
+        "/dev/shm",
+        Some("size=65536k".to_string()),
+    ));
+
+    let mut bin_paths = Vec::with_capacity(strategy.bin_paths.len());
+    for bin_path in strategy.bin_paths.iter() {
+        bin_paths.push(exposed_source(bin_path, project_dir)?);
+    }
+
+    // Start where the user was, as long as it is part of the project
+    let workdir = if workdir.starts_with(project_dir) {
+        workdir
+    } else {
+        project_dir
+    };
+
+    Ok(Plan {
+        create,
+        mounts,
+        chrooted,
+        landlock,
+        workdir: workdir.to_path_buf(),
+        bin_paths,
+    })
+}
+
+impl fmt::Display for Mount {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mode = match self.mode {
+            MountMode::RO => "ro",
+            MountMode::RW => "rw",
+        };
+        write!(f, "{} {:<9} {}", mode, self.kind, self.target.display())?;
+        let virtual_fs = !self.source.is_absolute();
+        if !virtual_fs && self.source != self.target {
+            write!(f, " <- {}", self.source.display())?;
+        }
+        if let Some(ref data) = self.data {
+            write!(f, " ({})", data)?;
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for dir in self.create.iter() {
+            writeln!(f, "mkdir {}", dir.display())?;
+        }
+        for mount in self.mounts.iter() {
+            writeln!(f, "{}", mount)?;
+        }
+        writeln!(f, "chroot")?;
+        for mount in self.chrooted.iter() {
+            writeln!(f, "{}", mount)?;
+        }
+        for path in self.landlock.ro.iter() {
+            writeln!(f, "landlock ro {}", path.display())?;
+        }
+        for path in self.landlock.rw.iter() {
+            writeln!(f, "landlock rw {}", path.display())?;
+        }
+        for path in self.bin_paths.iter() {
+            writeln!(f, "path {}", path.display())?;
+        }
+        writeln!(f, "workdir {}", self.workdir.display())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::Builder as TempBuilder;
+
+    fn config(contents: &str) -> Config {
+        toml::from_str(contents).expect("unable to parse config")
+    }
+
+    #[test]
+    fn order() {
+        // Outside of /tmp, which landlock lets the sandbox write to
+        let parent = Path::new(env!("CARGO_MANIFEST_DIR")).join("target");
+        fs::create_dir_all(&parent).expect("target directory");
+        let project = TempBuilder::new().tempdir_in(&parent).expect("tempdir");
+        let project_dir = project.path();
+        fs::create_dir(project_dir.join(".git")).unwrap();
+        fs::write(project_dir.join(".laurnrc"), b"").unwrap();
+        fs::write(project_dir.join(".env"), b"SECRET=1").unwrap();
+
+        let deps = vec![PathBuf::from("/nix/store/abc-bash")];
+        let config = config(
+            "[laurn]\nmode = \"none\"\nproject = \"ro\"\nwritable = [\"target\"]\nhide = [\".env\"]\n",
+        );
+        let plan = plan_with(&deps, project_dir, &config, None, None).unwrap();
+
+        assert_eq!(plan.create, vec![project_dir.join("target")]);
+        let mounts: Vec<(Kind, MountMode, &Path)> = plan
+            .mounts
+            .iter()
+            .take(8)
+            .map(|m| (m.kind, m.mode, m.target.as_path()))
+            .collect();
+        assert_eq!(
+            mounts,
+            vec![
+                (Kind::Nix, MountMode::RO, Path::new("/nix/store/abc-bash")),
+                (Kind::Host, MountMode::RW, Path::new("/etc/resolv.conf")),
+                (Kind::Project, MountMode::RO, project_dir),
+                // laurn.nix and nix/ do not exist
+                (Kind::Project, MountMode::RO, &project_dir.join(".git")),
+                (Kind::Project, MountMode::RO, &project_dir.join(".laurnrc")),
+                // Not created yet, but planned to be
+                (Kind::Project, MountMode::RW, &project_dir.join("target")),
+                // The protected paths again, over anything mounted since
+                (Kind::Project, MountMode::RO, &project_dir.join(".git")),
+                (Kind::Project, MountMode::RO, &project_dir.join(".laurnrc")),
+            ]
+        );
+        assert!(plan.mounts.iter().any(|m| m.kind == Kind::Tmpfs));
+        assert_eq!(plan.chrooted[0].kind, Kind::Proc);
+        assert!(plan
+            .chrooted
+            .iter()
+            .any(|m| m.kind == Kind::Hidden && m.target == project_dir.join(".env")));
+        assert_eq!(plan.workdir, project_dir);
+        // Declared to landlock, existing or not
+        assert!(plan.landlock.ro.contains(&project_dir.join("laurn.nix")));
+        assert!(plan.landlock.rw.contains(&project_dir.join("target")));
+        assert!(plan.landlock.ro.contains(&PathBuf::from("/sys")));
+        // Only the devices are writable, not the rest of /dev nor /proc
+        assert!(plan.landlock.ro.contains(&PathBuf::from("/proc")));
+        assert!(plan.landlock.ro.contains(&PathBuf::from("/dev")));
+        assert!(plan.landlock.rw.contains(&PathBuf::from("/dev/null")));
+        assert!(!plan
+            .landlock
+            .rw
+            .iter()
+            .any(|p| p == Path::new("/proc") || p == Path::new("/dev")));
+
+        // Read-only rules beneath a writable project would not restrict anything, the mounts
+        // alone keep these paths read-only
+        let rw = self::config("[laurn]\nmode = \"none\"\n");
+        let plan = plan_with(&deps, project_dir, &rw, None, None).unwrap();
+        assert!(plan.landlock.rw.iter().any(|p| p == project_dir));
+        assert!(!plan.landlock.ro.iter().any(|p| p.starts_with(project_dir)));
+        assert!(plan
+            .mounts
+            .iter()
+            .any(|m| m.target == project_dir.join(".git") && m.mode == MountMode::RO));
+
+        let sys = Path::new("/sys");
+        let rw = self::config("[laurn]\nmode = \"none\"\n\n[masking]\nsys = \"rw\"\n");
+        let plan = plan_with(&deps, project_dir, &rw, None, None).unwrap();
+        assert!(plan.landlock.rw.iter().any(|p| p == sys));
+        assert!(!plan.landlock.ro.iter().any(|p| p == sys));
+        let empty = self::config("[laurn]\nmode = \"none\"\n\n[masking]\nsys = \"empty\"\n");
+        let plan = plan_with(&deps, project_dir, &empty, None, None).unwrap();
+        assert!(!plan.mounts.iter().any(|m| m.kind == Kind::Sys));
+        assert!(!plan
+            .landlock
+            .ro
+            .iter()
+            .chain(plan.landlock.rw.iter())
+            .any(|p| p == sys));
+
+        // Volumes can not replace what the sandbox must not write to
+        for target in [".git/hooks", "."].iter() {
+            let volume = self::config(&format!(
+                "[laurn]\nmode = \"none\"\nvolumes = {{ cache = \"{}\" }}\n",
+                target
+            ));
+            assert!(matches!(
+                plan_with(&deps, project_dir, &volume, None, None),
+                Err(RunError::Volume(_))
+            ));
+        }
+
+        // The target of a volume is created before the project is mounted read-only
+        let volume = self::config(
+            "[laurn]\nmode = \"none\"\nproject = \"ro\"\nvolumes = { deps = \"node_modules\" }\n",
+        );
+        let plan = plan_with(&deps, project_dir, &volume, None, None).unwrap();
+        let target = project_dir.join("node_modules");
+        assert!(!target.exists());
+        assert!(plan.create.contains(&target));
+        assert!(plan
+            .mounts
+            .iter()
+            .any(|m| m.kind == Kind::Volume && m.target == target));
+
+        // Mount options can not be smuggled in the size
+        let tmp = self::config("[laurn]\nmode = \"none\"\n\n[tmp]\nsize = \"1m,uid=0\"\n");
+        match plan_with(&deps, project_dir, &tmp, None, None) {
+            Err(RunError::TmpSize(size)) => assert_eq!(size, "1m,uid=0"),
+            other => panic!("unexpected {:?}", other.map(|_| ())),
+        }
+
+        let overlay = Overlay::at(Path::new("/tmp/laurn.session"));
+        let plan = plan_with(&deps, project_dir, &config, None, Some(&overlay)).unwrap();
+        assert_eq!(plan.mounts[2].kind, Kind::Overlay);
+        // Writable directories would escape the overlay
+        assert!(!plan
+            .mounts
+            .iter()
+            .any(|m| m.target == project_dir.join("target")));
+
+        let text = plan.to_string();
+        assert!(text.contains("rw tmpfs     /tmp (size=536870912,mode=1777)"));
+        assert!(text.contains("\nchroot\nrw proc      /proc\n"));
+
+        let json = crate::render::json(&toml::Value::try_from(&plan).unwrap());
+        assert!(json.contains(
+            "{\"kind\":\"proc\",\"mode\":\"rw\",\"source\":\"proc\",\"target\":\"/proc\"}"
+        ));
+    }
+
+    #[test]
+    fn workspace() {
+        let root = TempBuilder::new().tempdir().expect("tempdir");
+        let root = root.path();
+        for member in ["services/api", "services/web"].iter() {
+            fs::create_dir_all(root.join(member).join("nix")).unwrap();
+            fs::write(root.join(member).join(".laurnrc"), b"").unwrap();
+            fs::write(root.join(member).join("laurn.nix"), b"").unwrap();
+        }
+        fs::write(root.join(".laurnrc"), b"").unwrap();
+
+        let api = root.join("services/api");
+        let workspace = Workspace {
+            root: root.to_path_buf(),
+            workdir: api.clone(),
+            config_dirs: vec![api.clone(), root.to_path_buf()],
+        };
+        let config = config("[laurn]\nmode = \"none\"\n");
+        let plan = plan_with(&[], &api, &config, Some(&workspace), None).unwrap();
+
+        // The sibling can not be poisoned from the sandbox of api
+        let web = root.join("services/web");
+        for path in [".laurnrc", "laurn.nix", "nix"].iter() {
+            assert!(plan
+                .mounts
+                .iter()
+                .any(|m| m.target == web.join(path) && m.mode == MountMode::RO));
+        }
+        assert_eq!(plan.workdir, api);
+    }
+}
@@ -0,0 +1,168 @@
+/// Render the configuration (and what laurn derives from it) for humans and tools
+///
+/// Everything goes through `toml::Value`: the JSON output does not need its own serializer.
+use std::fmt::Write as _;
+
+use serde::Serialize;
+
+use crate::config::Origins;
+
+/// Render the configuration as TOML, each value annotated with its origin
+pub fn annotated_toml<T: Serialize>(
+    config: &T,
+    origins: &Origins,
+) -> Result<String, toml::ser::Error> {
+    let value = toml::Value::try_from(config)?;
+    let mut out = String::new();
+
+    let table = match value.as_table() {
+        Some(table) => table,
+        None => return Ok(inline(&value)),
+    };
+
+    // Top-level values need to come before the sections
+    for (key, value) in table.iter().filter(|(_, v)| !v.is_table()) {
+        line(&mut out, key, value, origins.get(key));
+    }
+    for (name, section) in table.iter() {
+        if let Some(section) = section.as_table() {
+            let _ = writeln!(out, "\n[{}]", name);
+            for (key, value) in section.iter() {
+                line(
+                    &mut out,
+                    key,
+                    value,
+                    origins.get(&format!("{}.{}", name, key)),
+                );
+            }
+        }
+    }
+
+    Ok(out)
+}
+
+fn line(out: &mut String, key: &str, value: &toml::Value, origin: Option<&String>) {
+    let _ = write!(out, "{} = {}", key, inline(value));
+    if let Some(origin) = origin {
+        let _ = write!(out, "  # {}", origin);
+    }
+    out.push('\n');
+}
+
+/// A value on a single line, tables as inline tables
+fn inline(value: &toml::Value) -> String {
+    match value {
+        toml::Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .map(|(key, value)| format!("{} = {}", quote_key(key), inline(value)))
+                .collect();
+            if entries.is_empty() {
+                "{}".to_string()
+            } else {
+                format!("{{ {} }}", entries.join(", "))
+            }
+        }
+        toml::Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(inline).collect();
+            format!("[{}]", values.join(", "))
+        }
+        value => value.to_string(),
+    }
+}
+
+fn quote_key(key: &str) -> String {
+    let bare = !key.is_empty()
+        && key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if bare {
+        key.to_string()
+    } else {
+        json_string(key)
+    }
+}
+
+/// Render the configuration as JSON, `{"config": ..., "origins": ...}`
+pub fn annotated_json<T: Serialize>(
+    config: &T,
+    origins: &Origins,
+) -> Result<String, toml::ser::Error> {
+    let mut table = toml::value::Table::new();
+    table.insert("config".to_string(), toml::Value::try_from(config)?);
+    table.insert("origins".to_string(), toml::Value::try_from(origins)?);
+    Ok(json(&toml::Value::Table(table)))
+}
+
+/// Render a value as JSON
+pub fn json(value: &toml::Value) -> String {
+    match value {
+        toml::Value::String(s) => json_string(s),
+        toml::Value::Integer(i) => i.to_string(),
+        toml::Value::Float(f) if f.is_finite() => f.to_string(),
+        toml::Value::Float(_) => "null".to_string(),
+        toml::Value::Boolean(b) => b.to_string(),
+        toml::Value::Datetime(d) => json_string(&d.to_string()),
+        toml::Value::Array(values) => {
+            let values: Vec<String> = values.iter().map(json).collect();
+            format!("[{}]", values.join(","))
+        }
+        toml::Value::Table(table) => {
+            let entries: Vec<String> = table
+                .iter()
+                .map(|(key, value)| format!("{}:{}", json_string(key), json(value)))
+                .collect();
+            format!("{{{}}}", entries.join(","))
+        }
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn render() {
+        let mut config = Config::default();
+        config
+            .laurn
+            .volumes
+            .insert("cargo".to_string(), "~/.cargo/registry".to_string());
+        let mut origins = Origins::new();
+        origins.insert("laurn.mode".to_string(), "/project/.laurnrc".to_string());
+
+        let toml = annotated_toml(&config, &origins).unwrap();
+        assert!(toml.starts_with("\n[laurn]\n"));
+        assert!(toml.contains("\nmode = [\"none\"]  # /project/.laurnrc\n"));
+        assert!(toml.contains("volumes = { cargo = \"~/.cargo/registry\" }\n"));
+        assert!(toml.contains("\n[tmp]\n"));
+
+        let json = annotated_json(&config, &origins).unwrap();
+        assert!(json.starts_with("{\"config\":{\"laurn\":{"));
+        assert!(json.contains("\"mode\":[\"none\"]"));
+        assert!(json.ends_with("\"origins\":{\"laurn.mode\":\"/project/.laurnrc\"}}"));
+
+        assert_eq!(json_string("a\"b\\\n\u{1}"), "\"a\\\"b\\\\\\n\\u0001\"");
+    }
+}
@@ -1,53 +1,231 @@
+use std::env::{self, JoinPathsError};
+use std::error::Error;
 use std::ffi::{CStr, CString, NulError, OsStr};
+use std::fmt;
 use std::fs;
 use std::io::Error as IOError;
 use std::os::unix::ffi::OsStrExt;
 use std::path::{Component, Path, PathBuf};
 
 use nix::errno::Errno;
+use nix::libc;
 use nix::mount::{mount, MsFlags};
 use nix::sched::{unshare, CloneFlags};
-use nix::sys::stat::{self, mknod, stat, Mode, SFlag};
+use nix::sys::stat::{self, lstat, mknod, stat, Mode, SFlag};
+use nix::sys::statvfs::{statvfs, FsFlags};
 use nix::sys::wait::{waitpid, WaitStatus};
 use nix::unistd::{self, getgid, getuid, Gid, Pid, Uid};
 use nix::Error as SysError;
 
 use tempfile::Builder as TempBuilder;
 
-use home::home_dir;
-
-use crate::config::{Config, NetworkConfig};
+use crate::cgroup::{CgroupError, Session};
+use crate::config::{Config, NetworkConfig, Rlimit, Rlimits};
 use crate::container::{Container, Error as ContainerError};
-use crate::strategy::{ExposedPath, Strategy};
+use crate::landlock::{Ruleset, Status as LandlockStatus};
+use crate::overlay::{Ephemeral, Overlay};
+use crate::plan::{self, Kind, MountMode, Plan};
+use crate::strategy::StrategyError;
 use crate::utils::PathMerge;
+use crate::volumes::{self, VolumeError};
+
+/// Exit code reported when the kernel OOM-killed a process of the session
+pub const EXIT_OOM_KILLED: i32 = 250;
+
+/// Where the sandbox is rooted and started, by default the directory of the `laurn.nix`
+#[derive(Debug, Clone)]
+pub struct Workspace {
+    /// Directory mounted in the sandbox
+    pub root: PathBuf,
+    /// Directory the command starts in
+    pub workdir: PathBuf,
+    /// Directories of the `.laurnrc` files the configuration was read from
+    pub config_dirs: Vec<PathBuf>,
+}
 
 #[derive(Debug)]
 pub enum RunError {
     Dependencies(ContainerError),
     System(SysError),
     TempDir(IOError),
-    Mount(SysError),
+    /// Mounting `source` (a path or a filesystem) on `target` failed
+    Mount {
+        source: PathBuf,
+        target: PathBuf,
+        error: SysError,
+    },
+    /// The mountpoint could not be created
+    Mountpoint(PathBuf, SysError),
+    /// Unable to read the mounts of the sandbox
+    Mountinfo(PathBuf, IOError),
+    /// Only directories, regular files, character devices and sockets can be mounted
+    FileType(PathBuf),
     Wait(SysError),
+    /// The sandbox process was traced, or is still running
+    WaitStatus(WaitStatus),
     Chroot(SysError),
     Fork(SysError),
-    Stat(SysError),
+    Stat(PathBuf, SysError),
     Exec(SysError),
+    Landlock(SysError),
+    Cgroup(CgroupError),
+    Rlimit(SysError),
+    Strategy(StrategyError),
+    /// `tmp.size` is not a size
+    TmpSize(String),
 
     Collect(IOError),
     AsRoot(IOError),
 
     Mkdir,
     GuessHomeDir,
+    GuessDataDir,
+    Hide(IOError),
+    /// Unable to look for the sub-projects of the workspace
+    Members(IOError),
+    Writable(PathBuf, IOError),
+    Overlay(IOError),
+    Volume(VolumeError),
 
     /// Command includes null bytes in the middle
     Nul(NulError),
+    /// A directory to add to the PATH includes a colon
+    BinPath(JoinPathsError),
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RunError::Dependencies(ref e) => {
+                write!(
+                    f,
+                    "unable to list the dependencies of the environment: {}",
+                    e
+                )
+            }
+            RunError::System(ref e) => write!(
+                f,
+                "unable to create the namespaces of the sandbox: {} (see `laurn doctor`)",
+                e
+            ),
+            RunError::TempDir(ref e) => {
+                write!(f, "unable to create the working directory: {}", e)
+            }
+            RunError::Mount {
+                ref source,
+                ref target,
+                ref error,
+            } => write!(
+                f,
+                "unable to mount {} on {}: {}",
+                source.display(),
+                target.display(),
+                error
+            ),
+            RunError::Mountpoint(ref path, ref e) => {
+                write!(
+                    f,
+                    "unable to create the mountpoint {}: {}",
+                    path.display(),
+                    e
+                )
+            }
+            RunError::Mountinfo(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            RunError::FileType(ref path) => write!(
+                f,
+                "unable to mount {}: unsupported type of file",
+                path.display()
+            ),
+            RunError::Wait(ref e) => write!(f, "unable to wait for the sandbox: {}", e),
+            RunError::WaitStatus(WaitStatus::StillAlive) => {
+                write!(f, "the sandbox is still running")
+            }
+            RunError::WaitStatus(_) => write!(f, "the sandbox is traced by another process"),
+            RunError::Chroot(ref e) => write!(f, "unable to enter the sandbox: {}", e),
+            RunError::Fork(ref e) => write!(f, "unable to fork: {}", e),
+            RunError::Stat(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            RunError::Exec(ref e) => write!(f, "unable to start the command: {}", e),
+            RunError::Landlock(ref e) => write!(f, "unable to enforce landlock: {}", e),
+            RunError::Cgroup(ref e) => write!(f, "{}", e),
+            RunError::Rlimit(ref e) => write!(f, "unable to set the rlimits: {}", e),
+            RunError::Strategy(ref e) => write!(f, "{}", e),
+            RunError::TmpSize(ref size) => write!(f, "invalid tmp.size {:?}", size),
+            RunError::Collect(ref e) => {
+                write!(f, "unable to remove the working directory: {}", e)
+            }
+            RunError::AsRoot(ref e) => write!(f, "unable to map the user to root: {}", e),
+            RunError::Mkdir => write!(f, "laurn.nix has no parent directory"),
+            RunError::GuessHomeDir => write!(f, "unable to find the home directory"),
+            RunError::GuessDataDir => write!(f, "unable to find the data directory"),
+            RunError::Hide(ref e) => write!(f, "unable to look for the files to hide: {}", e),
+            RunError::Members(ref e) => write!(
+                f,
+                "unable to look for the sub-projects of the workspace: {}",
+                e
+            ),
+            RunError::Writable(ref path, ref e) => write!(
+                f,
+                "unable to create the writable directory {}: {}",
+                path.display(),
+                e
+            ),
+            RunError::Overlay(ref e) => write!(f, "unable to prepare the overlay: {}", e),
+            RunError::Volume(ref e) => write!(f, "{}", e),
+            RunError::Nul(_) => write!(f, "the command contains a null byte"),
+            RunError::BinPath(_) => write!(f, "a directory added to the PATH contains a colon"),
+        }
+    }
+}
+
+impl Error for RunError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            RunError::Dependencies(ref e) => Some(e),
+            RunError::System(ref e)
+            | RunError::Mount { error: ref e, .. }
+            | RunError::Mountpoint(_, ref e)
+            | RunError::Wait(ref e)
+            | RunError::Chroot(ref e)
+            | RunError::Fork(ref e)
+            | RunError::Stat(_, ref e)
+            | RunError::Exec(ref e)
+            | RunError::Landlock(ref e)
+            | RunError::Rlimit(ref e) => Some(e),
+            RunError::TempDir(ref e)
+            | RunError::Mountinfo(_, ref e)
+            | RunError::Collect(ref e)
+            | RunError::AsRoot(ref e)
+            | RunError::Hide(ref e)
+            | RunError::Members(ref e)
+            | RunError::Writable(_, ref e)
+            | RunError::Overlay(ref e) => Some(e),
+            RunError::Cgroup(ref e) => Some(e),
+            RunError::Strategy(ref e) => Some(e),
+            RunError::Volume(ref e) => Some(e),
+            RunError::Nul(ref e) => Some(e),
+            RunError::BinPath(ref e) => Some(e),
+            RunError::FileType(_)
+            | RunError::WaitStatus(_)
+            | RunError::TmpSize(_)
+            | RunError::Mkdir
+            | RunError::GuessHomeDir
+            | RunError::GuessDataDir => None,
+        }
+    }
 }
 
+/// Run the command in the sandbox, returns its exit code
+///
+/// The sandbox is set up by forked processes: an error there is returned in the child, whose
+/// caller exits with the code of the error (see `laurn` in src/bin). The parent only sees that
+/// exit code, and passes it through as the status of the command.
 pub fn run<'e, I: Iterator<Item = &'e str>>(
     container: Container,
     config: Config,
     command: Option<&mut I>,
     run_as_root: bool,
+    ephemeral: Option<Ephemeral>,
+    workspace: Option<&Workspace>,
 ) -> Result<i32, RunError> {
     // Here is how it's gonna go:
     //   - First process is going to do the workdir
@@ -61,23 +239,70 @@ pub fn run<'e, I: Iterator<Item = &'e str>>(
         .tempdir()
         .map_err(RunError::TempDir)?;
 
-    let working_dir_path = working_dir.path();
+    // The root of the sandbox is kept apart from the upper directory of the overlay
+    let root_dir = working_dir.path().join("root");
+    fs::create_dir(root_dir.as_path()).map_err(RunError::TempDir)?;
+
+    let overlay = match ephemeral {
+        Some(ref ephemeral) => {
+            Some(Overlay::create(working_dir.path(), ephemeral).map_err(RunError::Overlay)?)
+        }
+        None => None,
+    };
+
+    // The cgroup needs to be created from outside the user namespace, where we have the
+    // permissions on the cgroup delegated by systemd
+    let cgroup = Session::create(&config.limits).map_err(RunError::Cgroup)?;
 
     // First fork
     match unistd::fork() {
         Ok(unistd::ForkResult::Parent { child, .. }) => {
             let res = wait_child(child);
 
+            if let Some(ref overlay) = overlay {
+                overlay.cleanup();
+                if let Some(keep_changes) = ephemeral.and_then(|e| e.keep_changes) {
+                    eprintln!(
+                        "laurn: changes kept in {}, see `laurn diff {}`",
+                        overlay.upper.display(),
+                        keep_changes.display()
+                    );
+                }
+            }
+
             // The temp directory should only be collected once our child process returned and the
             // namespace is deleted
             drop(working_dir);
-            res
+
+            match cgroup {
+                Some(ref cgroup) if cgroup.oom_killed() => {
+                    eprintln!("laurn: a process of the session was killed (out of memory)");
+                    // The command may have survived the loss of one of its children
+                    res.map(|code| if code == 0 { 0 } else { EXIT_OOM_KILLED })
+                }
+                _ => res,
+            }
         }
         Ok(unistd::ForkResult::Child) => {
-            let res = run_unshare(container, working_dir_path, config, command, run_as_root);
-
-            // This is not our responsability to destroy working_directory
+            let res = match cgroup {
+                Some(ref cgroup) => cgroup.enter().map_err(RunError::Cgroup),
+                None => Ok(()),
+            }
+            .and_then(|_| {
+                run_unshare(
+                    container,
+                    root_dir.as_path(),
+                    config,
+                    command,
+                    run_as_root,
+                    overlay.as_ref(),
+                    workspace,
+                )
+            });
+
+            // This is not our responsability to destroy working_directory nor the cgroup
             std::mem::forget(working_dir);
+            std::mem::forget(cgroup);
 
             res
         }
@@ -88,6 +313,21 @@ pub fn run<'e, I: Iterator<Item = &'e str>>(
     }
 }
 
+/// The mounts `run` would set up, without creating any namespace
+pub fn inspect(
+    container: &Container,
+    config: &Config,
+    ephemeral: Option<&Ephemeral>,
+    workspace: Option<&Workspace>,
+) -> Result<Plan, RunError> {
+    // The directory of the changes only exists once the session starts
+    let overlay = ephemeral.map(|ephemeral| match ephemeral.keep_changes {
+        Some(ref dir) => Overlay::at(dir),
+        None => Overlay::at(Path::new("<session>")),
+    });
+    plan::plan(container, config, workspace, overlay.as_ref())
+}
+
 type UidGid = (Uid, Gid);
 
 fn get_outside_id() -> UidGid {
@@ -121,6 +361,8 @@ fn run_unshare<'e, I: Iterator<Item = &'e str>>(
     config: Config,
     command: Option<&mut I>,
     run_as_root: bool,
+    overlay: Option<&Overlay>,
+    workspace: Option<&Workspace>,
 ) -> Result<i32, RunError> {
     let ug = if run_as_root {
         Some(get_outside_id())
@@ -147,7 +389,9 @@ fn run_unshare<'e, I: Iterator<Item = &'e str>>(
     // Second fork
     match unistd::fork() {
         Ok(unistd::ForkResult::Parent { child, .. }) => wait_child(child),
-        Ok(unistd::ForkResult::Child) => run_child(container, working_dir, config, command),
+        Ok(unistd::ForkResult::Child) => {
+            run_child(container, working_dir, config, command, overlay, workspace)
+        }
         Err(e) => {
             eprintln!("Fork failed");
             Err(RunError::Fork(e))
@@ -158,10 +402,12 @@ fn run_unshare<'e, I: Iterator<Item = &'e str>>(
 fn wait_child(child: Pid) -> Result<i32, RunError> {
     loop {
         match waitpid(child, None).map_err(RunError::Wait)? {
-            WaitStatus::Exited(_pid, res) => {
-                return Ok(res);
-            }
-            e => unimplemented!("unimplemented status check: {:?}", e),
+            WaitStatus::Exited(_pid, res) => return Ok(res),
+            // Follow the shell convention for processes killed by a signal
+            WaitStatus::Signaled(_pid, signal, _core_dumped) => return Ok(128 + signal as i32),
+            // The child is not done yet
+            WaitStatus::Stopped(..) | WaitStatus::Continued(_) => continue,
+            status => return Err(RunError::WaitStatus(status)),
         }
     }
 }
@@ -171,10 +417,14 @@ fn run_child<'e, I: Iterator<Item = &'e str>>(
     working_dir: &Path,
     config: Config,
     command: Option<&mut I>,
+    overlay: Option<&Overlay>,
+    workspace: Option<&Workspace>,
 ) -> Result<i32, RunError> {
-    let project_dir = container.laurn_expr.parent().ok_or(RunError::Mkdir)?;
-
-    let data: Option<&str> = None;
+    let plan = plan::plan(&container, &config, workspace, overlay)?;
+    let project_dir = match workspace {
+        Some(workspace) => workspace.root.as_path(),
+        None => container.laurn_expr.parent().ok_or(RunError::Mkdir)?,
+    };
 
     let mode = stat::Mode::S_IRWXU
         | stat::Mode::S_IRGRP
@@ -185,111 +435,92 @@ fn run_child<'e, I: Iterator<Item = &'e str>>(
     let fmode =
         stat::Mode::S_IRUSR | stat::Mode::S_IWUSR | stat::Mode::S_IRGRP | stat::Mode::S_IROTH;
 
-    let dependencies = container.references().map_err(RunError::Dependencies)?;
-
-    // First mount the nix dependencies (and the main shell "entrypoint"), read-only
-    for dep in dependencies.iter() {
-        let dep = NixPath(dep.as_path());
-
-        dep.mount(working_dir, project_dir, mode, fmode, MountMode::RO)?;
+    for path in plan.chrooted.iter().filter(|m| m.kind == Kind::Hidden) {
+        let path = path
+            .target
+            .strip_prefix(project_dir)
+            .unwrap_or(&path.target);
+        eprintln!("laurn: hiding {}", path.display());
     }
 
-    let resolv = PathBuf::from("/etc/resolv.conf");
-    let dep = NixPath(resolv.as_path()); // Meh, hackish
-                                         // TODO(baloo): on github, resolv.conf can't be be remounted, mount it RW for now as it's out
-                                         // of reach anyway
-    dep.mount(working_dir, project_dir, mode, fmode, MountMode::RW)?;
-
-    // Then mount the project itself
-    let project = ProjectPath(project_dir);
-    project.mount(working_dir, project_dir, mode, fmode, MountMode::RW)?;
-
-    // Depending on the configuration, we want to expose things from $HOME or project other things
-    // (the laurn config itself, git, ...)
-    let protected_paths = Strategy::from(config.laurn.mode);
-    for ro_path in protected_paths.ro_paths.iter() {
-        ro_path.mount(working_dir, project_dir, mode, fmode, MountMode::RO)?;
-    }
-    for rw_path in protected_paths.rw_paths.iter() {
-        rw_path.mount(working_dir, project_dir, mode, fmode, MountMode::RW)?;
+    // Writable directories need to exist to be mounted, volumes are created with their marker
+    for path in plan.create.iter() {
+        fs::create_dir_all(path).map_err(|e| RunError::Writable(path.clone(), e))?;
     }
+    volumes::prepare(project_dir, &config.laurn.volumes).map_err(RunError::Volume)?;
 
-    // Mount things required to run processes
-    let filesystems = vec![
-        working_dir.join("proc"),
-        working_dir.join("sys"),
-        working_dir.join("dev"),
-        working_dir.join("dev/pts"),
-        working_dir.join("dev/shm"),
-    ];
-    for fs in filesystems {
-        unistd::mkdir(&fs, mode).map_err(RunError::Mount)?;
+    // Mountpoints of the filesystems mounted once chrooted
+    for fs in ["proc", "sys", "dev", "dev/pts", "dev/shm"].iter() {
+        mkdirp(working_dir.join(fs).as_path(), mode)?;
     }
 
-    let devices = vec![
-        Dev("/dev/null"),
-        Dev("/dev/zero"),
-        Dev("/dev/full"),
-        Dev("/dev/random"),
-        Dev("/dev/urandom"),
-        Dev("/dev/tty"),
-        Dev("/dev/console"),
-    ];
-    for dev in devices.iter() {
-        // /dev items needs to be bind-mounted from host because we run in user-namespace and we
-        // can't mknod.
-        dev.mount(working_dir, project_dir, mode, fmode, MountMode::RW)?;
+    for entry in plan.mounts.iter() {
+        let target = working_dir.merge(entry.target.as_path());
+        match entry.kind {
+            Kind::Overlay | Kind::Tmpfs => {
+                let mount_flags = if entry.kind == Kind::Tmpfs {
+                    MsFlags::MS_NOSUID | MsFlags::MS_NODEV
+                } else {
+                    MsFlags::empty()
+                };
+                mkdirp(target.as_path(), mode)?;
+                mount(
+                    Some(entry.source.as_path()),
+                    target.as_path(),
+                    Some(entry.source.as_path()),
+                    mount_flags,
+                    entry.data.as_deref(),
+                )
+                .map_err(mount_error(&entry.source, &target))?;
+            }
+            _ => {
+                if let Some(p) = target.parent() {
+                    mkdirp(p, mode)?;
+                }
+                mount_target(
+                    entry.source.as_path(),
+                    target.as_path(),
+                    mode,
+                    fmode,
+                    entry.mode,
+                )?;
+            }
+        }
     }
 
-    // Only root can mount sysfs, we need to bindmount that
-    let sysfs = Dev("/sys");
-    // TODO: we get EPERM when trying to remount readonly, maybe worth figuring out why
-    sysfs.mount(working_dir, project_dir, mode, fmode, MountMode::RW)?;
+    if !plan.mounts.iter().any(|m| m.kind == Kind::Socket) {
+        env::remove_var("SSH_AUTH_SOCK");
+    }
 
     // And then just chroot and run from there
     unistd::chroot(working_dir).map_err(RunError::Chroot)?;
-    unistd::chdir(project_dir).map_err(RunError::Chroot)?;
+    unistd::chdir(plan.workdir.as_path()).map_err(RunError::Chroot)?;
 
-    let mount_flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
-    let proc_dir = Path::new("/proc");
-    mount(Some("proc"), proc_dir, Some("proc"), mount_flags, data).map_err(RunError::Mount)?;
+    for entry in plan.chrooted.iter() {
+        mount_chrooted(entry, mode, fmode)?;
+    }
 
-    let mount_flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
-    let devpts_dir = Path::new("/dev/pts");
-    let devpts_data = Some("mode=620,ptmxmode=666");
-    mount(
-        Some("devpts"),
-        devpts_dir,
-        Some("devpts"),
-        mount_flags,
-        devpts_data,
-    )
-    .map_err(RunError::Mount)?;
+    set_rlimits(&config.rlimits)?;
 
-    // `/dev/ptmx`. A bind-mount or symlink of the container's /dev/pts/ptmx.
-    mknod("/dev/ptmx", SFlag::S_IFREG, fmode, 0).map_err(RunError::Mount)?;
-    let mount_flags = MsFlags::MS_BIND | MsFlags::MS_PRIVATE | MsFlags::MS_REC;
-    let empty_fs: Option<&str> = None;
-    mount(
-        Some("/dev/pts/ptmx"),
-        "/dev/ptmx",
-        empty_fs,
-        mount_flags,
-        data,
-    )
-    .map_err(RunError::Mount)?;
+    // Last layer, restrict filesystem accesses to what we exposed
+    let ruleset = Ruleset {
+        ro: plan.landlock.ro,
+        rw: plan.landlock.rw,
+    };
+    match ruleset.restrict_self().map_err(RunError::Landlock)? {
+        LandlockStatus::Enforced(_abi) => {}
+        LandlockStatus::Unsupported => {
+            eprintln!("laurn: landlock is not supported by this kernel, relying on mounts only");
+        }
+    }
 
-    let mount_flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
-    let devshm_dir = Path::new("/dev/shm");
-    let devshm_data = Some("size=65536k");
-    mount(
-        Some("shm"),
-        devshm_dir,
-        Some("tmpfs"),
-        mount_flags,
-        devshm_data,
-    )
-    .map_err(RunError::Mount)?;
+    // Picked up by the shell wrapper, the one of the host would point outside the sandbox
+    if plan.bin_paths.is_empty() {
+        env::remove_var("LAURN_PATH");
+    } else {
+        let bin_paths = env::join_paths(plan.bin_paths.iter()).map_err(RunError::BinPath)?;
+        env::set_var("LAURN_PATH", bin_paths);
+    }
 
     // Adapt the nix-shell wrapper
     let shell_wrapper: &OsStr = container.output.output.as_path().as_ref();
@@ -304,7 +535,8 @@ fn run_child<'e, I: Iterator<Item = &'e str>>(
             }
             out
         }
-        None => vec![], // Technically this is wrong, we should prefix with the shell wrapper here too, but bash is forgoving (and I'm lazy)
+        // The wrapper starts an interactive shell without arguments
+        None => vec![CString::new("laurn-shell").map_err(RunError::Nul)?],
     };
     let command: Vec<&CStr> = command.iter().map(|s| s.as_c_str()).collect();
 
@@ -319,6 +551,53 @@ fn run_child<'e, I: Iterator<Item = &'e str>>(
     unreachable!("exec returned?");
 }
 
+fn set_rlimits(rlimits: &Rlimits) -> Result<(), RunError> {
+    let limits = [
+        ("nofile", libc::RLIMIT_NOFILE, rlimits.nofile),
+        ("core", libc::RLIMIT_CORE, rlimits.core),
+        ("nproc", libc::RLIMIT_NPROC, rlimits.nproc),
+        ("as", libc::RLIMIT_AS, rlimits.address_space),
+        ("cpu", libc::RLIMIT_CPU, rlimits.cpu),
+    ];
+
+    for (name, resource, value) in limits.iter() {
+        let value = match value {
+            Some(value) => value,
+            None => continue,
+        };
+        let mut limit = libc::rlimit {
+            rlim_cur: 0,
+            rlim_max: 0,
+        };
+        if unsafe { libc::getrlimit(*resource, &mut limit) } != 0 {
+            return Err(RunError::Rlimit(SysError::last()));
+        }
+
+        // Without privileges, the hard limit can only be lowered
+        let hard = limit.rlim_max;
+        let value = match *value {
+            Rlimit::Limit(value) if (value as libc::rlim_t) > hard => {
+                eprintln!(
+                    "laurn: rlimits.{} lowered to the hard limit of laurn ({})",
+                    name, hard
+                );
+                hard
+            }
+            Rlimit::Limit(value) => value as libc::rlim_t,
+            Rlimit::Unlimited => hard,
+        };
+        let limit = libc::rlimit {
+            rlim_cur: value,
+            rlim_max: value,
+        };
+        if unsafe { libc::setrlimit(*resource, &limit) } != 0 {
+            return Err(RunError::Rlimit(SysError::last()));
+        }
+    }
+
+    Ok(())
+}
+
 fn mkdirp(target: &Path, mode: Mode) -> Result<(), RunError> {
     let mut cur = PathBuf::new();
 
@@ -329,131 +608,22 @@ fn mkdirp(target: &Path, mode: Mode) -> Result<(), RunError> {
                 cur.push(path);
                 match unistd::mkdir(cur.as_path(), mode) {
                     Ok(_) => continue,
-                    Err(e) if e == SysError::Sys(Errno::EEXIST) => continue,
-                    Err(e) => return Err(RunError::Mount(e)),
+                    Err(SysError::Sys(Errno::EEXIST)) => continue,
+                    Err(e) => return Err(RunError::Mountpoint(cur, e)),
                 }
             }
-            _ => panic!("should not yield curdir or whatever"),
-        }
-    }
-
-    Ok(())
-}
-
-trait Mount {
-    fn mount(
-        &self,
-        root_dir: &Path,
-        project_dir: &Path,
-        mode: Mode,
-        fmode: Mode,
-        mount_mode: MountMode,
-    ) -> Result<(), RunError>;
-}
-
-impl Mount for ExposedPath {
-    fn mount(
-        &self,
-        root_dir: &Path,
-        project_dir: &Path,
-        mode: Mode,
-        fmode: Mode,
-        mount_mode: MountMode,
-    ) -> Result<(), RunError> {
-        let (source_path, target_path) = match *self {
-            ExposedPath::Project(ref pp) => Ok((
-                project_dir.merge(pp.as_path()),
-                root_dir.merge(project_dir).merge(pp.as_path()),
-            )),
-            ExposedPath::UserHome(ref up) => {
-                let home = home_dir().ok_or(RunError::GuessHomeDir)?;
-                Ok((
-                    home.as_path().merge(up),
-                    root_dir.merge(home.as_path()).merge(up.as_path()),
+            Component::CurDir => continue,
+            // Mountpoints are absolute, and their targets never go up
+            Component::ParentDir | Component::Prefix(_) => {
+                return Err(RunError::Mountpoint(
+                    target.to_path_buf(),
+                    SysError::Sys(Errno::EINVAL),
                 ))
             }
-        }?;
-
-        if !source_path.exists() {
-            return Ok(());
-        }
-
-        if let Some(p) = target_path.parent() {
-            mkdirp(p, mode)?;
-        }
-
-        mount_target(
-            source_path.as_path(),
-            target_path.as_path(),
-            mode,
-            fmode,
-            mount_mode,
-        )
-    }
-}
-
-struct NixPath<'p>(&'p Path);
-
-impl<'p> Mount for NixPath<'p> {
-    fn mount(
-        &self,
-        root_dir: &Path,
-        _project_dir: &Path,
-        mode: Mode,
-        fmode: Mode,
-        mount_mode: MountMode,
-    ) -> Result<(), RunError> {
-        let (source_path, target_path) = (self.0, root_dir.merge(self.0));
-
-        if let Some(p) = target_path.parent() {
-            mkdirp(p, mode)?;
         }
-
-        mount_target(source_path, target_path.as_path(), mode, fmode, mount_mode)
-    }
-}
-
-struct ProjectPath<'p>(&'p Path);
-
-impl<'p> Mount for ProjectPath<'p> {
-    fn mount(
-        &self,
-        root_dir: &Path,
-        _project_dir: &Path,
-        mode: Mode,
-        fmode: Mode,
-        mount_mode: MountMode,
-    ) -> Result<(), RunError> {
-        let (source_path, target_path) = (self.0, root_dir.merge(self.0));
-
-        if let Some(p) = target_path.parent() {
-            mkdirp(p, mode)?;
-        }
-
-        mount_target(source_path, target_path.as_path(), mode, fmode, mount_mode)
     }
-}
 
-struct Dev<'p>(&'p str);
-
-impl<'p> Mount for Dev<'p> {
-    fn mount(
-        &self,
-        root_dir: &Path,
-        _project_dir: &Path,
-        mode: Mode,
-        fmode: Mode,
-        mount_mode: MountMode,
-    ) -> Result<(), RunError> {
-        let p = PathBuf::from(self.0);
-        let (source_path, target_path) = (p.as_path(), root_dir.merge(p.as_path()));
-
-        if let Some(p) = target_path.parent() {
-            mkdirp(p, mode)?;
-        }
-
-        mount_target(source_path, target_path.as_path(), mode, fmode, mount_mode)
-    }
+    Ok(())
 }
 
 fn mount_target(
@@ -463,17 +633,17 @@ fn mount_target(
     fmode: Mode,
     mount_mode: MountMode,
 ) -> Result<(), RunError> {
-    let info = stat(source_path).map_err(RunError::Stat)?;
+    let info = stat(source_path).map_err(|e| RunError::Stat(source_path.to_path_buf(), e))?;
 
     match SFlag::from_bits_truncate(info.st_mode) {
         SFlag::S_IFDIR => {
             match unistd::mkdir(target_path, mode) {
                 Ok(_) => {}
-                Err(e) if e == SysError::Sys(Errno::EEXIST) => {}
-                Err(e) => return Err(RunError::Mount(e)),
+                Err(SysError::Sys(Errno::EEXIST)) => {}
+                Err(e) => return Err(RunError::Mountpoint(target_path.to_path_buf(), e)),
             };
         }
-        SFlag::S_IFREG | SFlag::S_IFCHR => {
+        SFlag::S_IFREG | SFlag::S_IFCHR | SFlag::S_IFSOCK => {
             // mknod(2) can be used to create (empty) files, no need to open/close
             // in which case, dev is to be ignored (hence 0)
             match mknod(target_path, SFlag::S_IFREG, fmode, 0) {
@@ -481,13 +651,11 @@ fn mount_target(
                 // If target path already exist, then fine
                 // if it's a directly we won't be able to mount a file atop of it
                 // and it will fail on the mount below
-                Err(e) if e == SysError::Sys(Errno::EEXIST) => {}
-                Err(e) => return Err(RunError::Mount(e)),
+                Err(SysError::Sys(Errno::EEXIST)) => {}
+                Err(e) => return Err(RunError::Mountpoint(target_path.to_path_buf(), e)),
             };
         }
-        mode => {
-            unimplemented!("unimplemented dependency of type {:?}", mode);
-        }
+        _ => return Err(RunError::FileType(source_path.to_path_buf())),
     }
 
     match mount_mode {
@@ -497,7 +665,7 @@ fn mount_target(
 
             let mount_flags = MsFlags::MS_BIND | MsFlags::MS_PRIVATE | MsFlags::MS_REC;
             mount(Some(source_path), target_path, empty_fs, mount_flags, data)
-                .map_err(RunError::Mount)?;
+                .map_err(mount_error(source_path, target_path))?;
         }
         MountMode::RO => {
             let data: Option<&str> = None;
@@ -505,21 +673,231 @@ fn mount_target(
 
             let mount_flags = MsFlags::MS_BIND | MsFlags::MS_PRIVATE | MsFlags::MS_REC;
             mount(Some(source_path), target_path, empty_fs, mount_flags, data)
-                .map_err(RunError::Mount)?;
+                .map_err(mount_error(source_path, target_path))?;
 
             // Mount need to be issued twice for readonly
-            let mount_flags =
-                MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_PRIVATE | MsFlags::MS_BIND;
-            let empty_source: Option<&str> = None;
-            mount(empty_source, target_path, empty_fs, mount_flags, data)
-                .map_err(RunError::Mount)?;
+            remount_ro_recursive(target_path)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Execute an entry of the plan which needs to be mounted from within the chroot
+fn mount_chrooted(entry: &plan::Mount, mode: Mode, fmode: Mode) -> Result<(), RunError> {
+    let target = entry.target.as_path();
+    let mount_flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+
+    match entry.kind {
+        Kind::Mask | Kind::Hidden => mask_path(target),
+        Kind::ReadOnly => readonly_path(target),
+        Kind::Proc | Kind::Devpts | Kind::Shm => {
+            mount(
+                Some(entry.source.as_path()),
+                target,
+                Some(entry.source.as_path()),
+                mount_flags,
+                entry.data.as_deref(),
+            )
+            .map_err(mount_error(&entry.source, target))?;
+            if entry.kind != Kind::Devpts {
+                return Ok(());
+            }
+
+            // `/dev/ptmx`. A bind-mount or symlink of the container's /dev/pts/ptmx.
+            let data: Option<&str> = None;
+            let ptmx = Path::new("/dev/ptmx");
+            mknod(ptmx, SFlag::S_IFREG, fmode, 0)
+                .map_err(|e| RunError::Mountpoint(ptmx.to_path_buf(), e))?;
+            let mount_flags = MsFlags::MS_BIND | MsFlags::MS_PRIVATE | MsFlags::MS_REC;
+            let empty_fs: Option<&str> = None;
+            let source = Path::new("/dev/pts/ptmx");
+            mount(Some(source), ptmx, empty_fs, mount_flags, data)
+                .map_err(mount_error(source, ptmx))
+        }
+        _ => mount_target(entry.source.as_path(), target, mode, fmode, entry.mode),
+    }
+}
+
+fn mount_error<'p>(source: &'p Path, target: &'p Path) -> impl FnOnce(SysError) -> RunError + 'p {
+    move |error| RunError::Mount {
+        source: source.to_path_buf(),
+        target: target.to_path_buf(),
+        error,
+    }
+}
+
+/// Remount a bind mount read-only
+fn remount_ro(target_path: &Path) -> Result<(), RunError> {
+    let data: Option<&str> = None;
+    let empty_fs: Option<&str> = None;
+    let empty_source: Option<&str> = None;
+
+    // Flags of a mount inherited from the parent namespace are locked, the remount needs to keep
+    // them or the kernel will refuse it with EPERM.
+    let locked = statvfs(target_path)
+        .map_err(|e| RunError::Stat(target_path.to_path_buf(), e))?
+        .flags();
+    let mut mount_flags =
+        MsFlags::MS_RDONLY | MsFlags::MS_REMOUNT | MsFlags::MS_PRIVATE | MsFlags::MS_BIND;
+    for (fs_flag, ms_flag) in [
+        (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+        (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+        (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+        (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+        (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+        (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+    ]
+    .iter()
+    {
+        if locked.contains(*fs_flag) {
+            mount_flags |= *ms_flag;
         }
     }
 
+    mount(empty_source, target_path, empty_fs, mount_flags, data)
+        .map_err(mount_error(target_path, target_path))
+}
+
+/// Remount a recursive bind mount read-only, with every mount below it
+///
+/// MS_RDONLY only applies to the mount it is given, the submounts brought by MS_REC (such as
+/// `/sys/fs/cgroup`) would stay writable otherwise.
+fn remount_ro_recursive(target_path: &Path) -> Result<(), RunError> {
+    remount_ro(target_path)?;
+
+    let mountinfo_path = Path::new("/proc/self/mountinfo");
+    let mountinfo = fs::read_to_string(mountinfo_path)
+        .map_err(|e| RunError::Mountinfo(mountinfo_path.to_path_buf(), e))?;
+    for submount in submounts(&mountinfo, target_path) {
+        match remount_ro(&submount) {
+            Ok(()) => {}
+            // Unreachable from the sandbox as well
+            Err(RunError::Stat(_, SysError::Sys(Errno::EACCES))) => {}
+            Err(e) => return Err(e),
+        }
+    }
     Ok(())
 }
 
-enum MountMode {
-    RW,
-    RO,
+/// Mount points strictly below `target` in the content of `/proc/self/mountinfo`, parents first
+fn submounts(mountinfo: &str, target: &Path) -> Vec<PathBuf> {
+    let mut out: Vec<PathBuf> = Vec::new();
+    for line in mountinfo.lines() {
+        // "36 35 98:0 /mnt1 /mnt/parent rw,noatime master:1 - ext3 /dev/root rw"
+        let mount_point = match line.split(' ').nth(4) {
+            Some(mount_point) => PathBuf::from(OsStr::from_bytes(&unescape(mount_point))),
+            None => continue,
+        };
+        if mount_point != target && mount_point.starts_with(target) && !out.contains(&mount_point) {
+            out.push(mount_point);
+        }
+    }
+    out
+}
+
+/// Undo the octal escapes of mountinfo (`\040` for a space)
+fn unescape(field: &str) -> Vec<u8> {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 4)
+            .filter(|_| bytes[i] == b'\\')
+            .and_then(|octal| u8::from_str_radix(std::str::from_utf8(octal).ok()?, 8).ok());
+        match escaped {
+            Some(byte) => {
+                out.push(byte);
+                i += 4;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    out
+}
+
+/// Hide a path from the sandbox, must be called after chroot
+///
+/// Files are replaced by `/dev/null`, directories by an empty read-only tmpfs. Symlinks are left
+/// alone: the mount would land on whatever they point to.
+fn mask_path(path: &Path) -> Result<(), RunError> {
+    let data: Option<&str> = None;
+    let empty_fs: Option<&str> = None;
+
+    let info = match lstat(path) {
+        Ok(info) => info,
+        Err(SysError::Sys(Errno::ENOENT)) => return Ok(()),
+        Err(e) => return Err(RunError::Stat(path.to_path_buf(), e)),
+    };
+
+    let file_type = SFlag::from_bits_truncate(info.st_mode) & SFlag::S_IFMT;
+    if file_type == SFlag::S_IFLNK {
+        return Ok(());
+    }
+    if file_type == SFlag::S_IFDIR {
+        let mount_flags = MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV;
+        mount(
+            Some("tmpfs"),
+            path,
+            Some("tmpfs"),
+            mount_flags,
+            Some("size=0"),
+        )
+        .map_err(mount_error(Path::new("tmpfs"), path))
+    } else {
+        let mount_flags = MsFlags::MS_BIND | MsFlags::MS_PRIVATE;
+        let null = Path::new("/dev/null");
+        mount(Some(null), path, empty_fs, mount_flags, data).map_err(mount_error(null, path))
+    }
+}
+
+/// Make a path of the sandbox read-only, must be called after chroot
+fn readonly_path(path: &Path) -> Result<(), RunError> {
+    let data: Option<&str> = None;
+    let empty_fs: Option<&str> = None;
+
+    if !path.exists() {
+        return Ok(());
+    }
+
+    let mount_flags = MsFlags::MS_BIND | MsFlags::MS_PRIVATE | MsFlags::MS_REC;
+    mount(Some(path), path, empty_fs, mount_flags, data).map_err(mount_error(path, path))?;
+    remount_ro_recursive(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn readonly_submounts() {
+        let mountinfo = "\
+22 1 0:21 / /sys rw,nosuid,nodev,noexec,relatime shared:7 - sysfs sysfs rw
+27 22 0:26 / /sys/fs/cgroup rw,nosuid,nodev,noexec,relatime shared:9 - cgroup2 cgroup2 rw
+40 22 0:35 / /sys/kernel/debug rw,nosuid,nodev,noexec,relatime shared:16 - debugfs debugfs rw
+41 1 0:36 / /sysroot rw,relatime - ext4 /dev/sda1 rw
+90 1 0:21 / /tmp/laurn.a/root/sys rw,nosuid,nodev,noexec,relatime - sysfs sysfs rw
+91 90 0:26 / /tmp/laurn.a/root/sys/fs/cgroup rw,nosuid,nodev,noexec,relatime - cgroup2 cgroup2 rw
+92 1 0:37 / /tmp/laurn.a/root/home/my\\040project rw - ext4 /dev/sda1 rw
+";
+        assert_eq!(
+            submounts(mountinfo, Path::new("/sys")),
+            vec![
+                PathBuf::from("/sys/fs/cgroup"),
+                PathBuf::from("/sys/kernel/debug")
+            ]
+        );
+        assert_eq!(
+            submounts(mountinfo, Path::new("/tmp/laurn.a/root/sys")),
+            vec![PathBuf::from("/tmp/laurn.a/root/sys/fs/cgroup")]
+        );
+        assert_eq!(
+            submounts(mountinfo, Path::new("/tmp/laurn.a/root/home")),
+            vec![PathBuf::from("/tmp/laurn.a/root/home/my project")]
+        );
+    }
 }
@@ -1,7 +1,9 @@
+use std::error::Error;
 use std::ffi::OsStr;
-use std::path::PathBuf;
+use std::fmt;
+use std::path::{Component, Path, PathBuf};
 
-use crate::config::Mode;
+use crate::config::{ConflictPolicy, Mode, ProjectMode};
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub(crate) enum ExposedPath {
@@ -16,22 +18,271 @@ impl ExposedPath {
     fn user_home<T: ?Sized + AsRef<OsStr>>(input: &T) -> Self {
         ExposedPath::UserHome(PathBuf::from(input))
     }
+
+    fn path(&self) -> &Path {
+        match *self {
+            ExposedPath::Project(ref p) | ExposedPath::UserHome(ref p) => p.as_path(),
+        }
+    }
+}
+
+/// Whether a relative path stays within the directory it is relative to
+fn is_inside(path: &Path) -> bool {
+    path.components()
+        .all(|c| matches!(c, Component::Normal(_) | Component::CurDir))
+}
+
+/// A path relative to the project, without its `.` components
+///
+/// `None` if it does not stay inside the project, or if it is the project itself.
+fn normalize(path: &Path) -> Option<PathBuf> {
+    if !is_inside(path) {
+        return None;
+    }
+    let path: PathBuf = path
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect();
+    if path.as_os_str().is_empty() {
+        None
+    } else {
+        Some(path)
+    }
+}
+
+/// Whether one of the paths is the other, or one of its parents
+fn overlaps(a: &Path, b: &Path) -> bool {
+    a.starts_with(b) || b.starts_with(a)
 }
 
+/// Paths of the project exposed read-only unless configured otherwise
+const PROTECTED_PATHS: &[&str] = &[".git", ".laurnrc", "laurn.nix", "nix"];
+
+/// The configuration of laurn itself, it can never be writable from the sandbox
+const LAURN_CONFIG_PATHS: &[&str] = &[".laurnrc", "laurn.nix"];
+
+/// The configuration of laurn and the nix files imported by laurn.nix, which can never be
+/// unprotected, in the project or in any of its members
+const UNPROTECTABLE_NAMES: &[&str] = &[".laurnrc", "laurn.nix", "nix"];
+
+/// The binaries rustup installs in .cargo/bin
+const RUSTUP_PROXIES: &[&str] = &[
+    "rustup",
+    "cargo",
+    "cargo-clippy",
+    "cargo-fmt",
+    "cargo-miri",
+    "clippy-driver",
+    "rls",
+    "rust-analyzer",
+    "rust-gdb",
+    "rust-gdbgui",
+    "rust-lldb",
+    "rustc",
+    "rustdoc",
+    "rustfmt",
+];
+
+#[derive(Debug)]
+pub enum StrategyError {
+    /// A path is exposed read-only by a mode and read-write by another
+    Conflict(PathBuf),
+    /// A protected path of the project can not be made writable
+    Protected(PathBuf),
+    /// Writable paths must be relative to the project and stay in it
+    OutsideProject(PathBuf),
+}
+
+impl fmt::Display for StrategyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StrategyError::Conflict(ref path) => write!(
+                f,
+                "{} is exposed read-only by a mode and read-write by another",
+                path.display()
+            ),
+            StrategyError::Protected(ref path) => {
+                write!(f, "{} is protected, it can not be writable", path.display())
+            }
+            StrategyError::OutsideProject(ref path) => {
+                write!(f, "{} is not inside the project", path.display())
+            }
+        }
+    }
+}
+
+impl Error for StrategyError {}
+
 #[derive(Debug)]
 pub(crate) struct Strategy {
+    /// Access to the project directory itself, the paths below are mounted on top of it
+    pub project: ProjectMode,
     pub ro_paths: Vec<ExposedPath>,
     pub rw_paths: Vec<ExposedPath>,
+    /// Paths holding credentials, masked if they happen to be exposed
+    pub hidden_paths: Vec<ExposedPath>,
+    /// Directories prepended to the PATH of the shell
+    pub bin_paths: Vec<ExposedPath>,
 }
 
 impl Strategy {
     fn new(mut ro_paths: Vec<ExposedPath>, rw_paths: Vec<ExposedPath>) -> Self {
-        ro_paths.push(ExposedPath::project(".git"));
-        ro_paths.push(ExposedPath::project(".laurnrc"));
-        ro_paths.push(ExposedPath::project("laurn.nix"));
-        ro_paths.push(ExposedPath::project("nix"));
+        ro_paths.extend(PROTECTED_PATHS.iter().map(ExposedPath::project));
+
+        Self {
+            project: ProjectMode::ReadWrite,
+            ro_paths,
+            rw_paths,
+            hidden_paths: Vec::new(),
+            bin_paths: Vec::new(),
+        }
+    }
+
+    fn hide(mut self, hidden_paths: Vec<ExposedPath>) -> Self {
+        self.hidden_paths.extend(hidden_paths);
+        self
+    }
+
+    fn bin(mut self, bin_paths: Vec<ExposedPath>) -> Self {
+        self.bin_paths.extend(bin_paths);
+        self
+    }
+
+    /// Add or remove paths of the project from the protected (read-only) paths
+    pub fn protect(
+        mut self,
+        protect: &[PathBuf],
+        unprotect: &[PathBuf],
+    ) -> Result<Self, StrategyError> {
+        for path in protect.iter() {
+            let path =
+                normalize(path).ok_or_else(|| StrategyError::OutsideProject(path.clone()))?;
+            extend_unique(&mut self.ro_paths, vec![ExposedPath::Project(path)]);
+        }
+
+        for path in unprotect.iter() {
+            // The whole path is checked, `nix/pins.nix` is as much a part of the configuration as
+            // `nix` itself
+            let is_config = path.components().any(|c| {
+                UNPROTECTABLE_NAMES
+                    .iter()
+                    .any(|name| c.as_os_str() == *name)
+            });
+            if is_config {
+                return Err(StrategyError::Protected(path.clone()));
+            }
+            if let Some(path) = normalize(path) {
+                let path = ExposedPath::Project(path);
+                self.ro_paths.retain(|p| *p != path);
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Protect the paths of a sub-project of a workspace, relative to the root of the workspace
+    pub fn member(mut self, member: &Path) -> Self {
+        if !member.as_os_str().is_empty() {
+            let paths = PROTECTED_PATHS
+                .iter()
+                .map(|p| ExposedPath::project(&member.join(p)))
+                .collect();
+            extend_unique(&mut self.ro_paths, paths);
+        }
+        self
+    }
 
-        Self { ro_paths, rw_paths }
+    /// The configuration of laurn in each of the directories (relative to the project), mounted
+    /// read-only on top of everything else
+    pub fn config_paths(dirs: &[PathBuf]) -> Vec<ExposedPath> {
+        dirs.iter()
+            .flat_map(|dir| {
+                LAURN_CONFIG_PATHS
+                    .iter()
+                    .map(move |p| ExposedPath::project(&dir.join(p)))
+            })
+            .collect()
+    }
+
+    /// Paths of the project kept read-only
+    pub fn protected_paths(&self) -> impl Iterator<Item = &Path> {
+        self.ro_paths.iter().filter_map(|p| match *p {
+            ExposedPath::Project(ref p) => Some(p.as_path()),
+            ExposedPath::UserHome(_) => None,
+        })
+    }
+
+    /// The protected path `path` (relative to the project) would make writable, if any
+    pub fn covers_protected(&self, path: &Path) -> Option<&Path> {
+        self.protected_paths().find(|p| overlaps(path, p))
+    }
+
+    /// Set how the project is mounted, with the subdirectories that stay writable
+    ///
+    /// A writable directory can neither be a protected path, nor be inside or contain one.
+    pub fn project(
+        mut self,
+        project: ProjectMode,
+        writable: &[PathBuf],
+    ) -> Result<Self, StrategyError> {
+        for path in writable.iter() {
+            if path.components().any(|c| c == Component::ParentDir) || path.is_absolute() {
+                return Err(StrategyError::OutsideProject(path.clone()));
+            }
+            // The project itself
+            let normalized =
+                normalize(path).ok_or_else(|| StrategyError::Protected(path.clone()))?;
+            if self.covers_protected(&normalized).is_some() {
+                return Err(StrategyError::Protected(path.clone()));
+            }
+
+            let path = ExposedPath::Project(normalized);
+            if !self.rw_paths.contains(&path) {
+                self.rw_paths.push(path);
+            }
+        }
+        self.project = project;
+
+        Ok(self)
+    }
+
+    /// Strategy exposing everything required by each of the modes
+    pub fn from_modes(modes: &[Mode], policy: ConflictPolicy) -> Result<Self, StrategyError> {
+        let mut out = Strategy::default();
+        for mode in modes.iter() {
+            out = out.merge(Strategy::from(*mode), policy)?;
+        }
+        Ok(out)
+    }
+
+    /// Union of two strategies, without duplicates
+    pub fn merge(mut self, other: Strategy, policy: ConflictPolicy) -> Result<Self, StrategyError> {
+        extend_unique(&mut self.ro_paths, other.ro_paths);
+        extend_unique(&mut self.rw_paths, other.rw_paths);
+        extend_unique(&mut self.hidden_paths, other.hidden_paths);
+        extend_unique(&mut self.bin_paths, other.bin_paths);
+
+        if let Some(conflict) = self.ro_paths.iter().find(|p| self.rw_paths.contains(p)) {
+            match policy {
+                ConflictPolicy::ReadWrite => {
+                    let rw_paths = &self.rw_paths;
+                    self.ro_paths.retain(|p| !rw_paths.contains(p));
+                }
+                ConflictPolicy::Error => {
+                    return Err(StrategyError::Conflict(conflict.path().to_path_buf()));
+                }
+            }
+        }
+
+        Ok(self)
+    }
+}
+
+fn extend_unique(paths: &mut Vec<ExposedPath>, other: Vec<ExposedPath>) {
+    for path in other {
+        if !paths.contains(&path) {
+            paths.push(path);
+        }
     }
 }
 
@@ -45,7 +296,118 @@ impl From<Mode> for Strategy {
     fn from(mode: Mode) -> Strategy {
         match mode {
             Mode::None => Strategy::default(),
-            Mode::Rust => Strategy::new(vec![], vec![ExposedPath::user_home(".cargo")]),
+            // Only the caches are writable, build scripts should neither be able to read the
+            // registry token nor to plant a binary that would later run outside of laurn
+            Mode::Rust => Strategy::new(
+                vec![
+                    ExposedPath::user_home(".cargo/config.toml"),
+                    ExposedPath::user_home(".cargo/config"),
+                ],
+                vec![
+                    ExposedPath::user_home(".cargo/registry"),
+                    ExposedPath::user_home(".cargo/git"),
+                ],
+            )
+            .hide(vec![
+                ExposedPath::user_home(".cargo/credentials.toml"),
+                ExposedPath::user_home(".cargo/credentials"),
+            ]),
+            // `cargo install` puts its binaries next to the rustup proxies, only the proxies are
+            // mounted so that the sandbox can neither run nor replace the rest of .cargo/bin
+            Mode::Rustup => {
+                Strategy::new(
+                    std::iter::once(ExposedPath::user_home(".rustup"))
+                        .chain(RUSTUP_PROXIES.iter().map(|proxy| {
+                            ExposedPath::user_home(&Path::new(".cargo/bin").join(proxy))
+                        }))
+                        .collect(),
+                    vec![],
+                )
+                .bin(vec![ExposedPath::user_home(".cargo/bin")])
+            }
+            Mode::Node => Strategy::new(
+                vec![],
+                vec![
+                    ExposedPath::user_home(".npm/_cacache"),
+                    ExposedPath::user_home(".cache/yarn"),
+                    ExposedPath::user_home(".local/share/pnpm/store"),
+                ],
+            )
+            // npm and yarn store registry tokens there
+            .hide(vec![
+                ExposedPath::user_home(".npmrc"),
+                ExposedPath::user_home(".yarnrc"),
+                ExposedPath::user_home(".yarnrc.yml"),
+            ])
+            .bin(vec![ExposedPath::project("node_modules/.bin")]),
+            Mode::Python => Strategy::new(
+                vec![],
+                vec![
+                    ExposedPath::user_home(".cache/pip"),
+                    ExposedPath::user_home(".cache/pypoetry"),
+                    ExposedPath::user_home(".cache/uv"),
+                ],
+            )
+            // Index credentials
+            .hide(vec![
+                ExposedPath::user_home(".pypirc"),
+                ExposedPath::user_home(".config/pip"),
+                ExposedPath::user_home(".config/pypoetry"),
+            ])
+            // The virtualenv of the project, whether it already exists or not
+            .bin(vec![
+                ExposedPath::project(".venv/bin"),
+                ExposedPath::project("venv/bin"),
+            ]),
+            Mode::Go => Strategy::new(
+                vec![],
+                vec![
+                    ExposedPath::user_home("go/pkg/mod"),
+                    ExposedPath::user_home(".cache/go-build"),
+                ],
+            )
+            // Credentials for private modules
+            .hide(vec![ExposedPath::user_home(".netrc")]),
+            Mode::Jvm => Strategy::new(
+                vec![],
+                vec![
+                    ExposedPath::user_home(".m2/repository"),
+                    ExposedPath::user_home(".gradle/caches"),
+                    ExposedPath::user_home(".gradle/wrapper"),
+                ],
+            )
+            // Repository credentials
+            .hide(vec![
+                ExposedPath::user_home(".m2/settings.xml"),
+                ExposedPath::user_home(".m2/settings-security.xml"),
+                ExposedPath::user_home(".gradle/gradle.properties"),
+            ]),
+            // ~/.stack is shared by parts: the compilers of programs/ and config.yaml are run
+            // and read outside of the sandbox, they stay read-only so that a build cannot replace
+            // them, only the package caches are writable
+            Mode::Haskell => Strategy::new(
+                vec![
+                    ExposedPath::user_home(".stack/programs"),
+                    ExposedPath::user_home(".stack/config.yaml"),
+                    ExposedPath::user_home(".stack/global-project"),
+                ],
+                vec![
+                    // The cabal store, at its legacy and XDG locations
+                    ExposedPath::user_home(".cabal/store"),
+                    ExposedPath::user_home(".cabal/packages"),
+                    ExposedPath::user_home(".local/state/cabal/store"),
+                    ExposedPath::user_home(".cache/cabal/packages"),
+                    ExposedPath::user_home(".stack/pantry"),
+                    ExposedPath::user_home(".stack/snapshots"),
+                    ExposedPath::user_home(".stack/indices"),
+                ],
+            )
+            // Hackage upload credentials
+            .hide(vec![
+                ExposedPath::user_home(".cabal/config"),
+                ExposedPath::user_home(".config/cabal/config"),
+                ExposedPath::user_home(".stack/upload"),
+            ]),
         }
     }
 }
@@ -60,12 +422,321 @@ mod tests {
         assert_eq!(
             strategy.ro_paths,
             vec![
+                ExposedPath::user_home(".cargo/config.toml"),
+                ExposedPath::user_home(".cargo/config"),
                 ExposedPath::project(".git"),
                 ExposedPath::project(".laurnrc"),
                 ExposedPath::project("laurn.nix"),
                 ExposedPath::project("nix"),
             ]
         );
-        assert_eq!(strategy.rw_paths, vec![ExposedPath::user_home(".cargo")]);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home(".cargo/registry"),
+                ExposedPath::user_home(".cargo/git"),
+            ]
+        );
+        assert_hidden(&strategy, ".cargo/credentials.toml");
+        assert_hidden(&strategy, ".cargo/credentials");
+        assert!(!strategy
+            .rw_paths
+            .contains(&ExposedPath::user_home(".cargo")));
+    }
+
+    #[test]
+    fn rustup() {
+        let strategy = Strategy::from_modes(&[Mode::Rust, Mode::Rustup], ConflictPolicy::Error)
+            .expect("rust and rustup do not conflict");
+        assert!(strategy
+            .ro_paths
+            .contains(&ExposedPath::user_home(".rustup")));
+        assert!(!strategy
+            .rw_paths
+            .contains(&ExposedPath::user_home(".rustup")));
+
+        // The proxies added to the PATH are mounted, not what `cargo install` put next to them
+        let strategy = Strategy::from(Mode::Rustup);
+        assert!(strategy
+            .ro_paths
+            .contains(&ExposedPath::user_home(".cargo/bin/cargo")));
+        assert!(!strategy
+            .ro_paths
+            .contains(&ExposedPath::user_home(".cargo/bin")));
+        assert_eq!(
+            strategy.bin_paths,
+            vec![ExposedPath::user_home(".cargo/bin")]
+        );
+    }
+
+    #[test]
+    fn node() {
+        let strategy = Strategy::from(Mode::Node);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home(".npm/_cacache"),
+                ExposedPath::user_home(".cache/yarn"),
+                ExposedPath::user_home(".local/share/pnpm/store"),
+            ]
+        );
+        assert!(!strategy
+            .rw_paths
+            .iter()
+            .chain(strategy.ro_paths.iter())
+            .any(|p| *p == ExposedPath::user_home(".npmrc")));
+        assert!(strategy
+            .hidden_paths
+            .contains(&ExposedPath::user_home(".npmrc")));
+        assert_eq!(
+            strategy.bin_paths,
+            vec![ExposedPath::project("node_modules/.bin")]
+        );
+    }
+
+    #[test]
+    fn python() {
+        let strategy = Strategy::from(Mode::Python);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home(".cache/pip"),
+                ExposedPath::user_home(".cache/pypoetry"),
+                ExposedPath::user_home(".cache/uv"),
+            ]
+        );
+        assert!(strategy
+            .hidden_paths
+            .contains(&ExposedPath::user_home(".pypirc")));
+        assert!(strategy
+            .hidden_paths
+            .contains(&ExposedPath::user_home(".config/pip")));
+        assert_eq!(
+            strategy.bin_paths,
+            vec![
+                ExposedPath::project(".venv/bin"),
+                ExposedPath::project("venv/bin"),
+            ]
+        );
+    }
+
+    #[test]
+    fn merge_modes() {
+        let strategy = Strategy::from_modes(&[Mode::Rust, Mode::Node], ConflictPolicy::Error)
+            .expect("rust and node do not conflict");
+        // Protected paths are only listed once
+        for protected in Strategy::default().ro_paths.iter() {
+            assert_eq!(
+                strategy.ro_paths.iter().filter(|p| *p == protected).count(),
+                1
+            );
+        }
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home(".cargo/registry"),
+                ExposedPath::user_home(".cargo/git"),
+                ExposedPath::user_home(".npm/_cacache"),
+                ExposedPath::user_home(".cache/yarn"),
+                ExposedPath::user_home(".local/share/pnpm/store"),
+            ]
+        );
+        assert_eq!(
+            strategy.bin_paths,
+            vec![ExposedPath::project("node_modules/.bin")]
+        );
+    }
+
+    #[test]
+    fn merge_conflict() {
+        let ro = || Strategy::new(vec![ExposedPath::user_home(".cache")], vec![]);
+        let rw = || Strategy::new(vec![], vec![ExposedPath::user_home(".cache")]);
+
+        let strategy = ro()
+            .merge(rw(), ConflictPolicy::ReadWrite)
+            .expect("read-write wins");
+        assert_eq!(strategy.rw_paths, vec![ExposedPath::user_home(".cache")]);
+        assert!(!strategy
+            .ro_paths
+            .contains(&ExposedPath::user_home(".cache")));
+
+        assert!(ro().merge(rw(), ConflictPolicy::Error).is_err());
+    }
+
+    #[test]
+    fn read_only_project() {
+        let writable = vec![PathBuf::from("target"), PathBuf::from("node_modules")];
+        let strategy = Strategy::from(Mode::None)
+            .project(ProjectMode::ReadOnly, &writable)
+            .expect("target and node_modules are not protected");
+        assert_eq!(strategy.project, ProjectMode::ReadOnly);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::project("target"),
+                ExposedPath::project("node_modules"),
+            ]
+        );
+
+        let writable = vec![PathBuf::from(".laurnrc")];
+        assert!(Strategy::from(Mode::None)
+            .project(ProjectMode::ReadOnly, &writable)
+            .is_err());
+
+        let writable = vec![PathBuf::from("../other")];
+        assert!(Strategy::from(Mode::None)
+            .project(ProjectMode::ReadOnly, &writable)
+            .is_err());
+
+        // Neither the protected paths nor what contains them, however they are spelled
+        for path in [
+            "./.git",
+            ".",
+            "",
+            "./",
+            "nix/..",
+            ".git/hooks",
+            "./nix/overlays",
+        ]
+        .iter()
+        {
+            match Strategy::from(Mode::None).project(ProjectMode::ReadOnly, &[PathBuf::from(path)])
+            {
+                Err(StrategyError::Protected(_)) | Err(StrategyError::OutsideProject(_)) => {}
+                other => panic!("{:?} was accepted: {:?}", path, other.map(|s| s.rw_paths)),
+            }
+        }
+        let strategy = Strategy::from(Mode::None)
+            .member(Path::new("services/api"))
+            .project(ProjectMode::ReadOnly, &[PathBuf::from("./target")])
+            .expect("target is not protected");
+        assert_eq!(strategy.rw_paths, vec![ExposedPath::project("target")]);
+        assert!(Strategy::from(Mode::None)
+            .member(Path::new("services/api"))
+            .project(ProjectMode::ReadOnly, &[PathBuf::from("services")])
+            .is_err());
+    }
+
+    #[test]
+    fn protect() {
+        let strategy = Strategy::from(Mode::None)
+            .protect(
+                &[
+                    PathBuf::from("flake.nix"),
+                    PathBuf::from(".github/workflows"),
+                ],
+                &[PathBuf::from(".git")],
+            )
+            .expect(".git is not the laurn configuration");
+        assert!(strategy
+            .ro_paths
+            .contains(&ExposedPath::project("flake.nix")));
+        assert!(strategy
+            .ro_paths
+            .contains(&ExposedPath::project(".github/workflows")));
+        assert!(!strategy.ro_paths.contains(&ExposedPath::project(".git")));
+
+        // .git can now be made writable
+        let strategy = strategy
+            .project(ProjectMode::ReadOnly, &[PathBuf::from(".git")])
+            .expect(".git is unprotected");
+        assert!(strategy.rw_paths.contains(&ExposedPath::project(".git")));
+
+        for config in [
+            ".laurnrc",
+            "laurn.nix",
+            "./laurn.nix",
+            "nix",
+            "nix/",
+            "nix/pins.nix",
+            "services/api/.laurnrc",
+            "services/api/nix",
+        ]
+        .iter()
+        {
+            assert!(Strategy::from(Mode::None)
+                .protect(&[], &[PathBuf::from(config)])
+                .is_err());
+        }
+    }
+
+    #[test]
+    fn member() {
+        let strategy = Strategy::from(Mode::None).member(Path::new("services/api"));
+        for path in [".git", "services/api/laurn.nix", "services/api/nix"].iter() {
+            assert!(strategy.ro_paths.contains(&ExposedPath::project(path)));
+        }
+        assert_eq!(
+            Strategy::config_paths(&[PathBuf::new(), PathBuf::from("services/api")]),
+            vec![
+                ExposedPath::project(".laurnrc"),
+                ExposedPath::project("laurn.nix"),
+                ExposedPath::project("services/api/.laurnrc"),
+                ExposedPath::project("services/api/laurn.nix"),
+            ]
+        );
+    }
+
+    /// Credentials must never be part of the exposed caches
+    fn assert_hidden(strategy: &Strategy, path: &str) {
+        let path = ExposedPath::user_home(path);
+        assert!(strategy.hidden_paths.contains(&path));
+        assert!(!strategy.rw_paths.contains(&path));
+        assert!(!strategy.ro_paths.contains(&path));
+    }
+
+    #[test]
+    fn go() {
+        let strategy = Strategy::from(Mode::Go);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home("go/pkg/mod"),
+                ExposedPath::user_home(".cache/go-build"),
+            ]
+        );
+        assert_hidden(&strategy, ".netrc");
+    }
+
+    #[test]
+    fn jvm() {
+        let strategy = Strategy::from(Mode::Jvm);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home(".m2/repository"),
+                ExposedPath::user_home(".gradle/caches"),
+                ExposedPath::user_home(".gradle/wrapper"),
+            ]
+        );
+        assert_hidden(&strategy, ".m2/settings.xml");
+        assert_hidden(&strategy, ".gradle/gradle.properties");
+    }
+
+    #[test]
+    fn haskell() {
+        let strategy = Strategy::from(Mode::Haskell);
+        assert_eq!(
+            strategy.rw_paths,
+            vec![
+                ExposedPath::user_home(".cabal/store"),
+                ExposedPath::user_home(".cabal/packages"),
+                ExposedPath::user_home(".local/state/cabal/store"),
+                ExposedPath::user_home(".cache/cabal/packages"),
+                ExposedPath::user_home(".stack/pantry"),
+                ExposedPath::user_home(".stack/snapshots"),
+                ExposedPath::user_home(".stack/indices"),
+            ]
+        );
+        // The compilers installed by stack are usable, not replaceable
+        assert!(strategy
+            .ro_paths
+            .contains(&ExposedPath::user_home(".stack/programs")));
+        assert!(!strategy
+            .rw_paths
+            .contains(&ExposedPath::user_home(".stack")));
+        assert_hidden(&strategy, ".cabal/config");
+        assert_hidden(&strategy, ".config/cabal/config");
+        assert_hidden(&strategy, ".stack/upload");
     }
 }
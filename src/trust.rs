@@ -0,0 +1,484 @@
+/// Trust database: the configuration of a project is only honoured once the user allowed it
+///
+/// With the shell hook, entering a freshly cloned repository starts `laurn shell` with whatever
+/// its `.laurnrc` asks for. `laurn allow` records the sha256 of the `.laurnrc` files, of
+/// `laurn.nix` and of the files it refers to (its path literals, followed from file to file) in
+/// `$XDG_DATA_HOME/laurn/trust/<project-hash>`, any change to them needs to be allowed again.
+/// The permissions they requested are recorded along, to show what changed.
+///
+/// An untrusted project started with `untrusted = "restrict"` gets an empty environment: its
+/// `laurn.nix` is never evaluated.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{
+    mode_name, Config, ConflictPolicy, NetworkConfig, Project, ProjectMode, Rlimit, Rlimits,
+    SysMode, Tmp, CONFIG_FILE, NIX_FILE,
+};
+use crate::utils::{data_dir, project_hash};
+
+#[derive(Debug)]
+pub enum TrustError {
+    GuessDataDir,
+    IO(PathBuf, io::Error),
+    Serialize(toml::ser::Error),
+}
+
+impl fmt::Display for TrustError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            TrustError::GuessDataDir => write!(f, "unable to find the data directory"),
+            TrustError::IO(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+            TrustError::Serialize(ref e) => write!(f, "unable to record the trust: {}", e),
+        }
+    }
+}
+
+impl Error for TrustError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            TrustError::IO(_, ref e) => Some(e),
+            TrustError::Serialize(ref e) => Some(e),
+            TrustError::GuessDataDir => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum TrustStatus {
+    Trusted,
+    /// The project was never allowed
+    New {
+        requested: Vec<String>,
+    },
+    /// The configuration changed since it was allowed
+    Changed {
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+/// What is recorded when a project is allowed
+#[derive(Debug, PartialEq, Eq)]
+struct Record {
+    /// sha256 of each file of the configuration and of the environment, by path
+    files: BTreeMap<String, String>,
+    permissions: Vec<String>,
+}
+
+impl Record {
+    fn of(project: &Project) -> Result<Self, TrustError> {
+        let mut paths: Vec<PathBuf> = project
+            .config_dirs
+            .iter()
+            .map(|dir| dir.join(CONFIG_FILE))
+            .collect();
+        imported_files(&project.nix_expr, &mut paths)?;
+
+        let mut files = BTreeMap::new();
+        for path in paths {
+            let contents = match fs::read(&path) {
+                Ok(contents) => contents,
+                // A missing laurn.nix is reported when building the environment
+                Err(e) if e.kind() == ErrorKind::NotFound => continue,
+                // Nix can not read it either
+                Err(e) if e.kind() == ErrorKind::PermissionDenied => continue,
+                Err(e) => return Err(TrustError::IO(path, e)),
+            };
+            files.insert(path.to_string_lossy().into_owned(), sha256(&contents));
+        }
+
+        Ok(Record {
+            files,
+            permissions: permissions(&project.config),
+        })
+    }
+
+    fn to_toml(&self) -> Result<String, TrustError> {
+        let mut table = toml::value::Table::new();
+        let files = self
+            .files
+            .iter()
+            .map(|(path, digest)| (path.clone(), toml::Value::String(digest.clone())))
+            .collect();
+        let permissions = self
+            .permissions
+            .iter()
+            .cloned()
+            .map(toml::Value::String)
+            .collect();
+        table.insert("files".to_string(), toml::Value::Table(files));
+        table.insert("permissions".to_string(), toml::Value::Array(permissions));
+        toml::to_string(&toml::Value::Table(table)).map_err(TrustError::Serialize)
+    }
+
+    /// A record which can not be read is as good as no record at all
+    fn from_toml(contents: &str) -> Option<Self> {
+        let value: toml::Value = toml::from_str(contents).ok()?;
+        let files = value
+            .get("files")?
+            .as_table()?
+            .iter()
+            .map(|(path, digest)| Some((path.clone(), digest.as_str()?.to_string())))
+            .collect::<Option<_>>()?;
+        let permissions = value
+            .get("permissions")?
+            .as_array()?
+            .iter()
+            .map(|p| p.as_str().map(String::from))
+            .collect::<Option<_>>()?;
+
+        Some(Record { files, permissions })
+    }
+}
+
+fn sha256(contents: &[u8]) -> String {
+    Sha256::digest(contents)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `nix_file` and the files of the project it refers to, recursively
+///
+/// Nix reads files through path literals (`import ./nix/sources.nix`, `builtins.readFile
+/// ./sources.json`), they are followed from one `.nix` file to the next. A directory stands for
+/// its `default.nix`.
+fn imported_files(nix_file: &Path, out: &mut Vec<PathBuf>) -> Result<(), TrustError> {
+    if out.iter().any(|path| path == nix_file) {
+        return Ok(());
+    }
+    out.push(nix_file.to_path_buf());
+
+    let contents = match fs::read(nix_file) {
+        Ok(contents) => contents,
+        // Recorded as missing or unreadable by the caller
+        Err(_) => return Ok(()),
+    };
+    let dir = nix_file.parent().unwrap_or_else(|| Path::new("/"));
+    for literal in path_literals(&contents) {
+        // Nix resolves `..` lexically, before following symlinks
+        let mut path = PathBuf::new();
+        for component in dir.join(literal).components() {
+            match component {
+                Component::CurDir => {}
+                Component::ParentDir => {
+                    path.pop();
+                }
+                component => path.push(component),
+            }
+        }
+        if path.is_dir() {
+            imported_files(&path.join("default.nix"), out)?;
+        } else if path.extension().is_some_and(|e| e == "nix") {
+            imported_files(&path, out)?;
+        } else if path.is_file() && !out.contains(&path) {
+            out.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Relative path literals (`./file`, `../dir/file`) of a nix expression
+fn path_literals(contents: &[u8]) -> Vec<&Path> {
+    let is_path = |c: u8| c.is_ascii_alphanumeric() || b"._-+/".contains(&c);
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < contents.len() {
+        let start = i;
+        while i < contents.len() && is_path(contents[i]) {
+            i += 1;
+        }
+        let word = &contents[start..i];
+        if word.starts_with(b"./") || word.starts_with(b"../") {
+            let word = match word.iter().rposition(|c| *c != b'/') {
+                Some(end) => &word[..=end],
+                None => word,
+            };
+            out.push(Path::new(std::ffi::OsStr::from_bytes(word)));
+        }
+        i += 1;
+    }
+    out
+}
+
+/// The environment of the projects which were not allowed, with nothing from the project
+pub fn restricted_environment() -> Result<PathBuf, TrustError> {
+    let dir = data_dir()
+        .ok_or(TrustError::GuessDataDir)?
+        .join("restricted");
+    fs::create_dir_all(&dir).map_err(|e| TrustError::IO(dir.clone(), e))?;
+    let path = dir.join(NIX_FILE);
+    fs::write(&path, RESTRICTED_ENVIRONMENT).map_err(|e| TrustError::IO(path.clone(), e))?;
+    Ok(path)
+}
+
+const RESTRICTED_ENVIRONMENT: &str = "# Environment of the projects laurn does not trust
+let
+  pkgs = import <nixpkgs> { };
+in pkgs.mkShell {
+  name = \"laurn-restricted\";
+  buildInputs = [ ];
+}
+";
+
+fn record_path(project_dir: &Path) -> Result<PathBuf, TrustError> {
+    data_dir()
+        .map(|dir| dir.join("trust").join(project_hash(project_dir)))
+        .ok_or(TrustError::GuessDataDir)
+}
+
+/// Whether the configuration of the project was allowed as it is
+pub fn check(project: &Project) -> Result<TrustStatus, TrustError> {
+    let current = Record::of(project)?;
+    let path = record_path(&project.root)?;
+    let previous = match fs::read_to_string(&path) {
+        Ok(contents) => Record::from_toml(&contents),
+        Err(e) if e.kind() == ErrorKind::NotFound => None,
+        Err(e) => return Err(TrustError::IO(path, e)),
+    };
+
+    Ok(status(previous.as_ref(), &current))
+}
+
+fn status(previous: Option<&Record>, current: &Record) -> TrustStatus {
+    match previous {
+        Some(previous) if previous.files == current.files => TrustStatus::Trusted,
+        Some(previous) => TrustStatus::Changed {
+            added: difference(&current.permissions, &previous.permissions),
+            removed: difference(&previous.permissions, &current.permissions),
+        },
+        None => TrustStatus::New {
+            requested: current.permissions.clone(),
+        },
+    }
+}
+
+fn difference(a: &[String], b: &[String]) -> Vec<String> {
+    a.iter().filter(|p| !b.contains(p)).cloned().collect()
+}
+
+/// Trust the configuration of the project as it is now
+pub fn allow(project: &Project) -> Result<(), TrustError> {
+    let record = Record::of(project)?.to_toml()?;
+    let path = record_path(&project.root)?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).map_err(|e| TrustError::IO(dir.to_path_buf(), e))?;
+    }
+    fs::write(&path, record.as_bytes()).map_err(|e| TrustError::IO(path, e))
+}
+
+/// Stop trusting the configuration of the project
+pub fn deny(project_dir: &Path) -> Result<(), TrustError> {
+    let path = record_path(project_dir)?;
+    match fs::remove_file(&path) {
+        Err(e) if e.kind() != ErrorKind::NotFound => Err(TrustError::IO(path, e)),
+        _ => Ok(()),
+    }
+}
+
+/// What the configuration asks for, one line per permission
+pub fn permissions(config: &Config) -> Vec<String> {
+    let laurn = &config.laurn;
+    let mut out = Vec::new();
+
+    let modes: Vec<&str> = laurn.mode.iter().map(|m| mode_name(*m)).collect();
+    out.push(format!("mode: {}", modes.join(", ")));
+    if laurn.network == NetworkConfig::Exposed {
+        out.push("network: exposed".to_string());
+    }
+    if laurn.project == ProjectMode::ReadOnly {
+        out.push("project: read-only".to_string());
+    }
+    if laurn.workspace {
+        out.push("workspace: the whole directory is mounted".to_string());
+    }
+    for path in laurn.writable.iter() {
+        out.push(format!("writable: {}", path.display()));
+    }
+    for path in laurn.unprotect.iter() {
+        out.push(format!("unprotect: {}", path.display()));
+    }
+    for path in laurn.protect.iter() {
+        out.push(format!("protect: {}", path.display()));
+    }
+    for pattern in laurn.hide.iter() {
+        out.push(format!("hide: {}", pattern));
+    }
+    for (name, target) in laurn.volumes.iter() {
+        out.push(format!("volume: {} on {}", name, target));
+    }
+
+    let limits = &config.limits;
+    if let Some(ref memory) = limits.memory {
+        out.push(format!("limits.memory: {}", memory));
+    }
+    if let Some(pids) = limits.pids {
+        out.push(format!("limits.pids: {}", pids));
+    }
+    if let Some(ref cpu) = limits.cpu {
+        out.push(format!("limits.cpu: {}", cpu));
+    }
+
+    if laurn.mode.len() > 1 && laurn.conflict == ConflictPolicy::ReadWrite {
+        out.push("conflict: read-write wins".to_string());
+    }
+
+    let rlimits = &config.rlimits;
+    let default = Rlimits::default();
+    for (name, value, default) in [
+        ("nofile", rlimits.nofile, default.nofile),
+        ("core", rlimits.core, default.core),
+        ("nproc", rlimits.nproc, default.nproc),
+        ("as", rlimits.address_space, default.address_space),
+        ("cpu", rlimits.cpu, default.cpu),
+    ]
+    .iter()
+    {
+        match *value {
+            _ if value == default => {}
+            Some(Rlimit::Limit(limit)) => out.push(format!("rlimits.{}: {}", name, limit)),
+            Some(Rlimit::Unlimited) => out.push(format!("rlimits.{}: unlimited", name)),
+            None => out.push(format!("rlimits.{}: not limited", name)),
+        }
+    }
+
+    match config.masking.sys {
+        SysMode::ReadOnly => {}
+        SysMode::ReadWrite => out.push("sys: read-write".to_string()),
+        SysMode::Empty => out.push("sys: empty".to_string()),
+    }
+    for path in config.masking.unmasked_paths.iter() {
+        out.push(format!("unmask: {}", path.display()));
+    }
+    for path in config.masking.masked_paths.iter() {
+        out.push(format!("mask: {}", path.display()));
+    }
+    for path in config.masking.readonly_paths.iter() {
+        out.push(format!("read-only: {}", path.display()));
+    }
+    if config.tmp.size != Tmp::default().size {
+        out.push(format!("tmp.size: {}", config.tmp.size));
+    }
+    if config.tmp.persist {
+        out.push("tmp: persistent".to_string());
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(laurnrc: &str) -> Record {
+        let config: Config = toml::from_str(laurnrc).expect("unable to parse config");
+        let mut files = BTreeMap::new();
+        files.insert("/project/.laurnrc".to_string(), sha256(laurnrc.as_bytes()));
+        Record {
+            files,
+            permissions: permissions(&config),
+        }
+    }
+
+    #[test]
+    fn changes() {
+        let allowed = record("[laurn]\nmode = \"rust\"\n");
+        let roundtrip = Record::from_toml(&allowed.to_toml().unwrap());
+        assert_eq!(roundtrip.as_ref(), Some(&allowed));
+
+        assert_eq!(status(Some(&allowed), &allowed), TrustStatus::Trusted);
+        assert_eq!(
+            status(None, &allowed),
+            TrustStatus::New {
+                requested: vec!["mode: rust".to_string()]
+            }
+        );
+
+        let changed =
+            record("[laurn]\nmode = \"rust\"\nnetwork = \"exposed\"\nunprotect = [\".git\"]\n");
+        assert_eq!(
+            status(Some(&allowed), &changed),
+            TrustStatus::Changed {
+                added: vec![
+                    "network: exposed".to_string(),
+                    "unprotect: .git".to_string()
+                ],
+                removed: vec![],
+            }
+        );
+
+        // Any change needs to be allowed, even one which does not change the permissions
+        let comment = record("# comment\n[laurn]\nmode = \"rust\"\n");
+        assert_eq!(
+            status(Some(&allowed), &comment),
+            TrustStatus::Changed {
+                added: vec![],
+                removed: vec![],
+            }
+        );
+    }
+
+    #[test]
+    fn evaluated() {
+        let root = tempfile::Builder::new().tempdir().expect("tempdir");
+        let root = root.path();
+        fs::create_dir_all(root.join("nix/pins")).unwrap();
+        fs::write(root.join(".laurnrc"), b"[laurn]\nmode = \"none\"\n").unwrap();
+        fs::write(
+            root.join("laurn.nix"),
+            b"import ./nix { } # see ../README.md",
+        )
+        .unwrap();
+        fs::write(
+            root.join("nix/default.nix"),
+            b"{ }: builtins.fromJSON (builtins.readFile ./pins/sources.json)",
+        )
+        .unwrap();
+        fs::write(root.join("nix/pins/sources.json"), [0xff, 0x00]).unwrap();
+        fs::write(root.join("nix/unused.nix"), b"{ }").unwrap();
+        fs::write(root.join("README.md"), b"").unwrap();
+        let project = Project {
+            root: root.to_path_buf(),
+            nix_expr: root.join("laurn.nix"),
+            config_dirs: vec![root.to_path_buf()],
+            config: Config::default(),
+            enforced: Vec::new(),
+            origins: Default::default(),
+        };
+
+        let allowed = Record::of(&project).unwrap();
+        let files: Vec<&str> = allowed.files.keys().map(String::as_str).collect();
+        // Neither nix/unused.nix nor ../README.md outside of the project
+        let mut expected: Vec<String> = [
+            ".laurnrc",
+            "laurn.nix",
+            "nix/default.nix",
+            "nix/pins/sources.json",
+        ]
+        .iter()
+        .map(|p| root.join(p).to_string_lossy().into_owned())
+        .collect();
+        expected.sort();
+        assert_eq!(
+            files,
+            expected.iter().map(String::as_str).collect::<Vec<_>>()
+        );
+        let roundtrip = Record::from_toml(&allowed.to_toml().unwrap());
+        assert_eq!(roundtrip.as_ref(), Some(&allowed));
+
+        // Editing what laurn.nix imports needs to be allowed again
+        fs::write(root.join("nix/pins/sources.json"), b"{}").unwrap();
+        assert_ne!(
+            status(Some(&allowed), &Record::of(&project).unwrap()),
+            TrustStatus::Trusted
+        );
+    }
+}
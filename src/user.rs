@@ -0,0 +1,561 @@
+/// User-global configuration, `$XDG_CONFIG_HOME/laurn/config.toml`
+///
+/// The `.laurnrc` of a project is written by whoever controls the repository. The user
+/// configuration holds the defaults and the policy of the user running laurn: the project can
+/// override the defaults, but never loosen the policy.
+///
+/// ```toml
+/// [defaults.laurn]     # same format as .laurnrc, the project overrides it
+/// mode = "rust"
+///
+/// [policy]             # always wins over the project
+/// untrusted = "refuse" # or "restrict", for projects which were not allowed
+/// network = ["~/work"] # projects allowed to expose the network
+/// hide = ["**/.env"]
+/// protect = [".git"]
+///
+/// [policy.limits]      # maximum limits, the project can only ask for less
+/// memory = "8G"
+///
+/// [policy.masking]     # the most the project can unmask
+/// sys = "ro"
+/// unmasked_paths = []
+///
+/// [policy.tmp]
+/// size = "1G"
+/// persist = false
+///
+/// [policy.rlimits]     # maximum rlimits, like [policy.limits]
+/// nofile = 4096
+///
+/// [session]            # only configurable by the user
+/// ssh_agent = true
+/// shell = "zsh"
+/// ```
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Component, Path, PathBuf};
+
+use home::home_dir;
+use serde_derive::{Deserialize, Serialize};
+
+use crate::config::{invalid, Config, ConfigError, Limits, NetworkConfig, Rlimit, SysMode};
+use crate::utils::config_dir;
+
+/// Name of the user configuration in the configuration directory of laurn
+pub const USER_CONFIG_FILE: &str = "config.toml";
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct UserConfig {
+    /// Defaults of every project, in the format of `.laurnrc`
+    pub defaults: Option<toml::Value>,
+    #[serde(default)]
+    pub policy: Policy,
+    #[serde(default)]
+    pub session: Session,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Policy {
+    /// Directories of the projects allowed to expose the network, any project if not set
+    pub network: Option<Vec<String>>,
+    /// Patterns hidden in every project, on top of the ones of the project
+    pub hide: Vec<String>,
+    /// Paths of every project kept read-only, whatever the project unprotects
+    pub protect: Vec<PathBuf>,
+    /// Maximum limits of a session
+    pub limits: Limits,
+    pub masking: MaskingPolicy,
+    pub tmp: TmpPolicy,
+    pub rlimits: RlimitsPolicy,
+    /// What to do with a project whose configuration was not allowed (`laurn allow`)
+    pub untrusted: Untrusted,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaskingPolicy {
+    /// The most `/sys` can be exposed, `empty` < `ro` < `rw`
+    pub sys: Option<SysMode>,
+    /// Default masked and read-only paths a project may unmask, any of them if not set
+    pub unmasked_paths: Option<Vec<PathBuf>>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct TmpPolicy {
+    /// Maximum size of each tmpfs, a persistent `/tmp` is not limited so it is refused too
+    pub size: Option<String>,
+    /// Whether a project may keep its `/tmp` between sessions
+    pub persist: Option<bool>,
+}
+
+/// Maximum rlimits of a session
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct RlimitsPolicy {
+    pub nofile: Option<Rlimit>,
+    pub core: Option<Rlimit>,
+    pub nproc: Option<Rlimit>,
+    #[serde(rename = "as")]
+    pub address_space: Option<Rlimit>,
+    pub cpu: Option<Rlimit>,
+}
+
+#[derive(Deserialize, PartialEq, Eq, Debug, Copy, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Untrusted {
+    /// Do not start the sandbox
+    #[default]
+    Refuse,
+    /// Ignore the configuration of the project, start the most restrictive sandbox
+    Restrict,
+}
+
+/// Options of the session the project has no say in
+#[derive(Deserialize, Serialize, Debug, Default, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Session {
+    /// Forward the ssh-agent of the user (`$SSH_AUTH_SOCK`) to the sandbox
+    pub ssh_agent: bool,
+    /// Shell started by `laurn shell`, it needs to be part of the environment
+    pub shell: Option<String>,
+}
+
+/// Load the configuration of the user, an absent file is an empty configuration
+pub fn load_user_config() -> Result<UserConfig, ConfigError> {
+    match config_dir() {
+        Some(dir) => load_user_config_from(&dir.join(USER_CONFIG_FILE)),
+        None => Ok(UserConfig::default()),
+    }
+}
+
+fn load_user_config_from(path: &Path) -> Result<UserConfig, ConfigError> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(UserConfig::default()),
+        Err(e) => return Err(ConfigError::IO(e)),
+    };
+    parse_user_config(path, &contents)
+}
+
+fn parse_user_config(path: &Path, contents: &str) -> Result<UserConfig, ConfigError> {
+    let user_config: UserConfig =
+        toml::from_str(contents).map_err(|e| ConfigError::Invalid(invalid(path, contents, &e)))?;
+
+    // The defaults are only merged with the configuration of projects later on, check them now
+    if let Some(ref defaults) = user_config.defaults {
+        defaults
+            .clone()
+            .try_into::<Config>()
+            .map_err(|e| ConfigError::Invalid(invalid(path, contents, &e)))?;
+    }
+
+    Ok(user_config)
+}
+
+impl UserConfig {
+    /// Apply the policy to the configuration of the project at `project_dir`
+    ///
+    /// Returns a description of what the policy changed.
+    pub fn enforce(&self, project_dir: &Path, config: &mut Config) -> Vec<String> {
+        let home = home_dir();
+        config.session = self.session.clone();
+        enforce(&self.policy, home.as_deref(), project_dir, config)
+    }
+}
+
+fn enforce(
+    policy: &Policy,
+    home: Option<&Path>,
+    project_dir: &Path,
+    config: &mut Config,
+) -> Vec<String> {
+    let mut out = Vec::new();
+
+    if let Some(ref allowed) = policy.network {
+        let allowed = allowed
+            .iter()
+            .map(|dir| expand_home(dir, home))
+            .any(|dir| project_dir.starts_with(dir));
+        if !allowed && config.laurn.network == NetworkConfig::Exposed {
+            config.laurn.network = NetworkConfig::Isolated;
+            out.push("network is isolated".to_string());
+        }
+    }
+
+    for pattern in policy.hide.iter() {
+        if !config.laurn.hide.contains(pattern) {
+            config.laurn.hide.push(pattern.clone());
+        }
+    }
+
+    for path in policy.protect.iter() {
+        if config.laurn.unprotect.contains(path) {
+            out.push(format!("{} stays protected", path.display()));
+        }
+        config.laurn.unprotect.retain(|p| p != path);
+        if !config.laurn.protect.contains(path) {
+            config.laurn.protect.push(path.clone());
+        }
+
+        // Nor can the path be made writable through a writable directory or a volume
+        let protected = project_dir.join(without_cur_dir(path));
+        let covers =
+            |target: &Path| protected.starts_with(target) || target.starts_with(&protected);
+        config.laurn.writable.retain(|writable| {
+            let covered = covers(&project_dir.join(without_cur_dir(writable)));
+            if covered {
+                out.push(format!("{} is not writable", writable.display()));
+            }
+            !covered
+        });
+        config.laurn.volumes.retain(|name, target| {
+            let target = match (target.strip_prefix("~/"), home) {
+                (Some(rel), Some(home)) => home.join(without_cur_dir(Path::new(rel))),
+                _ => project_dir.join(without_cur_dir(Path::new(target))),
+            };
+            let covered = covers(&target);
+            if covered {
+                out.push(format!("volume {} is not mounted", name));
+            }
+            !covered
+        });
+    }
+
+    let limits = &mut config.limits;
+    if let Some(ref max) = policy.limits.memory {
+        let requested = limits.memory.as_deref().and_then(parse_bytes);
+        if requested.is_none_or(|requested| Some(requested) > parse_bytes(max)) {
+            if limits.memory.is_some() {
+                out.push(format!("memory is limited to {}", max));
+            }
+            limits.memory = Some(max.clone());
+        }
+    }
+    if let Some(max) = policy.limits.pids {
+        if limits.pids.is_none_or(|requested| requested > max) {
+            if limits.pids.is_some() {
+                out.push(format!("pids are limited to {}", max));
+            }
+            limits.pids = Some(max);
+        }
+    }
+    if let Some(ref max) = policy.limits.cpu {
+        let requested = limits.cpu.as_deref().and_then(parse_cpu);
+        if requested.is_none_or(|requested| Some(requested) > parse_cpu(max)) {
+            if limits.cpu.is_some() {
+                out.push(format!("cpu is limited to {}", max));
+            }
+            limits.cpu = Some(max.clone());
+        }
+    }
+
+    let masking = &mut config.masking;
+    if let Some(max) = policy.masking.sys {
+        if exposure(masking.sys) > exposure(max) {
+            masking.sys = max;
+            out.push(format!("/sys is limited to {}", sys_name(max)));
+        }
+    }
+    if let Some(ref allowed) = policy.masking.unmasked_paths {
+        masking.unmasked_paths.retain(|path| {
+            let allowed = allowed.contains(path);
+            if !allowed {
+                out.push(format!("{} stays masked", path.display()));
+            }
+            allowed
+        });
+    }
+
+    let tmp = &mut config.tmp;
+    if let Some(ref max) = policy.tmp.size {
+        if parse_bytes(&tmp.size).is_none_or(|requested| Some(requested) > parse_bytes(max)) {
+            tmp.size = max.clone();
+            out.push(format!("tmp is limited to {}", max));
+        }
+    }
+    if tmp.persist && (policy.tmp.persist == Some(false) || policy.tmp.size.is_some()) {
+        tmp.persist = false;
+        out.push("tmp is not persisted".to_string());
+    }
+
+    let rlimits = &mut config.rlimits;
+    let max = &policy.rlimits;
+    for (name, requested, max) in [
+        ("nofile", &mut rlimits.nofile, max.nofile),
+        ("core", &mut rlimits.core, max.core),
+        ("nproc", &mut rlimits.nproc, max.nproc),
+        ("as", &mut rlimits.address_space, max.address_space),
+        ("cpu", &mut rlimits.cpu, max.cpu),
+    ] {
+        let max = match max {
+            Some(Rlimit::Limit(max)) => max,
+            // Unlimited is the most laurn can set anyway
+            Some(Rlimit::Unlimited) | None => continue,
+        };
+        match *requested {
+            Some(Rlimit::Limit(limit)) if limit <= max => {}
+            Some(_) => {
+                out.push(format!("rlimits.{} is limited to {}", name, max));
+                *requested = Some(Rlimit::Limit(max));
+            }
+            // Like limits, it applies even when the project does not ask for any
+            None => *requested = Some(Rlimit::Limit(max)),
+        }
+    }
+
+    out
+}
+
+/// How much of `/sys` is exposed
+fn exposure(sys: SysMode) -> u8 {
+    match sys {
+        SysMode::Empty => 0,
+        SysMode::ReadOnly => 1,
+        SysMode::ReadWrite => 2,
+    }
+}
+
+fn sys_name(sys: SysMode) -> &'static str {
+    match sys {
+        SysMode::Empty => "empty",
+        SysMode::ReadOnly => "ro",
+        SysMode::ReadWrite => "rw",
+    }
+}
+
+fn without_cur_dir(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|c| *c != Component::CurDir)
+        .collect()
+}
+
+/// `~/` is the home directory of the user
+fn expand_home(dir: &str, home: Option<&Path>) -> PathBuf {
+    match (dir.strip_prefix("~/"), home) {
+        (Some(rel), Some(home)) => home.join(rel),
+        _ => PathBuf::from(dir),
+    }
+}
+
+/// A memory size as accepted by cgroup `memory.max`: bytes with an optional K, M, G or T suffix,
+/// or `max`
+pub(crate) fn parse_bytes(size: &str) -> Option<u64> {
+    let size = size.trim();
+    if size == "max" {
+        return Some(u64::MAX);
+    }
+
+    let (number, shift) = match size.char_indices().last()? {
+        (i, 'k') | (i, 'K') => (&size[..i], 10),
+        (i, 'm') | (i, 'M') => (&size[..i], 20),
+        (i, 'g') | (i, 'G') => (&size[..i], 30),
+        (i, 't') | (i, 'T') => (&size[..i], 40),
+        _ => (size, 0),
+    };
+    number.parse::<u64>().ok()?.checked_mul(1 << shift)
+}
+
+/// The share of a cpu allowed by cgroup `cpu.max` (`$QUOTA $PERIOD`)
+pub(crate) fn parse_cpu(cpu: &str) -> Option<f64> {
+    let mut parts = cpu.split_whitespace();
+    let quota = parts.next()?;
+    let period = match parts.next() {
+        Some(period) => period.parse::<u64>().ok().filter(|p| *p > 0)?,
+        None => 100_000,
+    };
+    if parts.next().is_some() {
+        return None;
+    }
+    if quota == "max" {
+        Some(f64::INFINITY)
+    } else {
+        quota
+            .parse::<u64>()
+            .ok()
+            .map(|quota| quota as f64 / period as f64)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn project(contents: &str) -> Config {
+        toml::from_str(contents).expect("unable to parse config")
+    }
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_bytes("4096"), Some(4096));
+        assert_eq!(parse_bytes("4G"), Some(4 << 30));
+        assert_eq!(parse_bytes("512m"), Some(512 << 20));
+        assert_eq!(parse_bytes("max"), Some(u64::MAX));
+        assert_eq!(parse_bytes("lots"), None);
+
+        assert_eq!(parse_cpu("50000 100000"), Some(0.5));
+        assert_eq!(parse_cpu("200000"), Some(2.0));
+        assert_eq!(parse_cpu("max 100000"), Some(f64::INFINITY));
+        assert_eq!(parse_cpu("-1 100000"), None);
+        assert_eq!(parse_cpu("50000 0"), None);
+        assert_eq!(parse_cpu("50000 100000 1"), None);
+    }
+
+    #[test]
+    fn strict() {
+        let path = Path::new("config.toml");
+        assert!(parse_user_config(path, "[session]\nssh_agent = true\n").is_ok());
+        match parse_user_config(path, "[session]\nssh-agent = true\n") {
+            Err(ConfigError::Invalid(invalid)) => {
+                assert_eq!(invalid.line, 2);
+                assert_eq!(invalid.suggestion, Some("ssh_agent".to_string()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        match parse_user_config(
+            path,
+            "[defaults.laurn]\nmode = \"rust\"\nnetwrok = \"exposed\"\n",
+        ) {
+            Err(ConfigError::Invalid(invalid)) => {
+                assert_eq!(invalid.line, 3);
+                assert_eq!(invalid.suggestion, Some("network".to_string()));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn policy_wins() {
+        let policy: UserConfig = toml::from_str(
+            r#"
+[policy]
+network = ["~/work"]
+hide = ["**/.env"]
+protect = [".git"]
+
+[policy.limits]
+memory = "4G"
+pids = 512
+
+[session]
+ssh_agent = true
+"#,
+        )
+        .expect("unable to parse user config");
+        assert!(policy.session.ssh_agent);
+
+        let home = Path::new("/home/user");
+        let mut config = project(
+            r#"
+[laurn]
+mode = "none"
+network = "exposed"
+unprotect = [".git"]
+project = "ro"
+writable = ["./.git/hooks", "target"]
+volumes = { hooks = "./.git", cargo = "~/.cargo/registry" }
+
+[limits]
+memory = "64G"
+pids = 128
+"#,
+        );
+        let notes = enforce(
+            &policy.policy,
+            Some(home),
+            Path::new("/home/user/src/hostile"),
+            &mut config,
+        );
+
+        assert_eq!(config.laurn.network, NetworkConfig::Isolated);
+        assert_eq!(config.laurn.hide, vec!["**/.env".to_string()]);
+        assert!(config.laurn.unprotect.is_empty());
+        assert_eq!(config.laurn.protect, vec![PathBuf::from(".git")]);
+        assert_eq!(config.laurn.writable, vec![PathBuf::from("target")]);
+        assert_eq!(
+            config.laurn.volumes.keys().collect::<Vec<_>>(),
+            vec!["cargo"]
+        );
+        assert_eq!(config.limits.memory, Some("4G".to_string()));
+        // The project asks for less than the policy
+        assert_eq!(config.limits.pids, Some(128));
+        assert_eq!(notes.len(), 5);
+
+        let mut config = project("[laurn]\nmode = \"none\"\nnetwork = \"exposed\"\n");
+        enforce(
+            &policy.policy,
+            Some(home),
+            Path::new("/home/user/work/api"),
+            &mut config,
+        );
+        assert_eq!(config.laurn.network, NetworkConfig::Exposed);
+        // Limits apply even when the project does not ask for any
+        assert_eq!(config.limits.pids, Some(512));
+    }
+
+    #[test]
+    fn policy_bounds_the_sandbox() {
+        let policy: UserConfig = toml::from_str(
+            r#"
+[policy.masking]
+sys = "ro"
+unmasked_paths = ["/proc/kcore"]
+
+[policy.tmp]
+size = "1G"
+
+[policy.rlimits]
+nofile = 4096
+nproc = 1024
+"#,
+        )
+        .expect("unable to parse user config");
+
+        let mut config = project(
+            r#"
+[laurn]
+mode = "none"
+
+[masking]
+sys = "rw"
+unmasked_paths = ["/proc/kcore", "/proc/sysrq-trigger"]
+
+[tmp]
+size = "64G"
+persist = true
+
+[rlimits]
+nofile = "unlimited"
+cpu = 60
+"#,
+        );
+        let notes = enforce(
+            &policy.policy,
+            None,
+            Path::new("/home/user/src/hostile"),
+            &mut config,
+        );
+
+        assert_eq!(config.masking.sys, SysMode::ReadOnly);
+        assert_eq!(
+            config.masking.unmasked_paths,
+            vec![PathBuf::from("/proc/kcore")]
+        );
+        assert_eq!(config.tmp.size, "1G");
+        assert!(!config.tmp.persist);
+        assert_eq!(config.rlimits.nofile, Some(Rlimit::Limit(4096)));
+        // Applied even when the project does not ask for any
+        assert_eq!(config.rlimits.nproc, Some(Rlimit::Limit(1024)));
+        // Not covered by the policy
+        assert_eq!(config.rlimits.cpu, Some(Rlimit::Limit(60)));
+        assert_eq!(notes.len(), 5);
+
+        // Asking for less is fine
+        let mut config = project("[laurn]\nmode = \"none\"\n\n[masking]\nsys = \"empty\"\n");
+        assert!(enforce(&policy.policy, None, Path::new("/p"), &mut config).is_empty());
+        assert_eq!(config.masking.sys, SysMode::Empty);
+    }
+}
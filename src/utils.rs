@@ -1,5 +1,10 @@
+use std::env;
+use std::ffi::OsStr;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 
+use home::home_dir;
+
 pub(crate) trait PathMerge {
     fn merge(self, other: &Path) -> PathBuf;
 }
@@ -21,3 +26,48 @@ impl PathMerge for PathBuf {
         }
     }
 }
+
+/// Where laurn keeps its persistent data (`$XDG_DATA_HOME/laurn`)
+pub(crate) fn data_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("laurn")),
+        _ => home_dir().map(|home| home.join(".local/share/laurn")),
+    }
+}
+
+/// Where the user configures laurn (`$XDG_CONFIG_HOME/laurn`)
+pub(crate) fn config_dir() -> Option<PathBuf> {
+    match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("laurn")),
+        _ => home_dir().map(|home| home.join(".config/laurn")),
+    }
+}
+
+/// Stable identifier of a project, derived from its path
+///
+/// This is a FNV-1a hash, it only needs to be stable between laurn releases, not to resist
+/// collision attacks.
+pub(crate) fn project_hash(project_dir: &Path) -> String {
+    let path: &OsStr = project_dir.as_ref();
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in path.as_bytes() {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    format!("{:016x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_project_hash() {
+        assert_eq!(project_hash(Path::new("")), "cbf29ce484222325");
+        assert_eq!(project_hash(Path::new("a")), "af63dc4c8601ec8c");
+        assert_ne!(
+            project_hash(Path::new("/home/user/foo")),
+            project_hash(Path::new("/home/user/bar"))
+        );
+    }
+}
@@ -0,0 +1,296 @@
+/// Persistent per-project volumes managed by laurn
+///
+/// Volumes are stored in `$XDG_DATA_HOME/laurn/volumes/<project-hash>/<name>`, so that the
+/// caches of a project can not be poisoned by another one.
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io::{self, ErrorKind};
+use std::path::{Component, Path, PathBuf};
+
+use home::home_dir;
+
+use crate::utils::{data_dir, project_hash};
+
+/// Records which project a set of volumes belongs to, for `prune`
+const PROJECT_MARKER: &str = ".project";
+
+#[derive(Debug)]
+pub enum VolumeError {
+    /// Names are used as directory names, they can not contain `/` or start with a `.`
+    InvalidName(String),
+    NotFound(String),
+    /// Targets are relative to the project or the home directory (`~/`), and stay inside it
+    InvalidTarget(String, String),
+    /// The target would replace a protected path of the project, or the project itself
+    Protected(String, PathBuf),
+    GuessHomeDir,
+    GuessDataDir,
+    IO(PathBuf, io::Error),
+}
+
+impl fmt::Display for VolumeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VolumeError::InvalidName(ref name) => write!(
+                f,
+                "invalid volume name `{}`, it can not contain `/` or start with `.`",
+                name
+            ),
+            VolumeError::NotFound(ref name) => write!(f, "no volume named `{}`", name),
+            VolumeError::InvalidTarget(ref name, ref target) => write!(
+                f,
+                "invalid target `{}` for volume `{}`, it must be a path inside the project or \
+                 the home directory (`~/`)",
+                target, name
+            ),
+            VolumeError::Protected(ref name, ref path) => write!(
+                f,
+                "volume `{}` would be mounted over {}, which is protected",
+                name,
+                path.display()
+            ),
+            VolumeError::GuessHomeDir => write!(f, "unable to find the home directory"),
+            VolumeError::GuessDataDir => write!(f, "unable to find the data directory"),
+            VolumeError::IO(ref path, ref e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+impl Error for VolumeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match *self {
+            VolumeError::IO(_, ref e) => Some(e),
+            VolumeError::InvalidName(_)
+            | VolumeError::NotFound(_)
+            | VolumeError::InvalidTarget(..)
+            | VolumeError::Protected(..)
+            | VolumeError::GuessHomeDir
+            | VolumeError::GuessDataDir => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Volume {
+    pub name: String,
+    /// Where the volume is stored on the host
+    pub source: PathBuf,
+    /// Where the volume is mounted in the sandbox
+    pub target: PathBuf,
+}
+
+fn volumes_dir() -> Result<PathBuf, VolumeError> {
+    data_dir()
+        .map(|dir| dir.join("volumes"))
+        .ok_or(VolumeError::GuessDataDir)
+}
+
+fn project_volumes_dir(project_dir: &Path) -> Result<PathBuf, VolumeError> {
+    volumes_dir().map(|dir| dir.join(project_hash(project_dir)))
+}
+
+fn check_name(name: &str) -> Result<(), VolumeError> {
+    if name.is_empty() || name.starts_with('.') || name.contains('/') {
+        Err(VolumeError::InvalidName(name.to_string()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Targets starting with `~/` are relative to the home directory, other targets to the project.
+///
+/// Absolute paths, `..` and the directories themselves are refused.
+fn target(project_dir: &Path, name: &str, target: &str) -> Result<PathBuf, VolumeError> {
+    let (base, rel) = match target.strip_prefix("~/") {
+        Some(rel) => (home_dir().ok_or(VolumeError::GuessHomeDir)?, rel),
+        None => (project_dir.to_path_buf(), target),
+    };
+    let rel: PathBuf = Path::new(rel)
+        .components()
+        .filter(|c| *c != Component::CurDir)
+        .collect();
+    let inside = rel.components().all(|c| matches!(c, Component::Normal(_)));
+    if !inside || rel.as_os_str().is_empty() {
+        return Err(VolumeError::InvalidTarget(
+            name.to_string(),
+            target.to_string(),
+        ));
+    }
+    Ok(base.join(rel))
+}
+
+/// Resolve the volumes declared in the configuration, without creating them
+pub(crate) fn resolve(
+    project_dir: &Path,
+    declared: &BTreeMap<String, String>,
+) -> Result<Vec<Volume>, VolumeError> {
+    if declared.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let dir = project_volumes_dir(project_dir)?;
+    let mut out = Vec::with_capacity(declared.len());
+    for (name, path) in declared.iter() {
+        check_name(name)?;
+        out.push(Volume {
+            name: name.clone(),
+            source: dir.join(name),
+            target: target(project_dir, name, path)?,
+        });
+    }
+
+    Ok(out)
+}
+
+/// Resolve the volumes declared in the configuration, creating them if needed
+pub(crate) fn prepare(
+    project_dir: &Path,
+    declared: &BTreeMap<String, String>,
+) -> Result<Vec<Volume>, VolumeError> {
+    let volumes = resolve(project_dir, declared)?;
+    if volumes.is_empty() {
+        return Ok(volumes);
+    }
+
+    let dir = project_volumes_dir(project_dir)?;
+    fs::create_dir_all(&dir).map_err(|e| VolumeError::IO(dir.clone(), e))?;
+    let marker = dir.join(PROJECT_MARKER);
+    fs::write(&marker, project_dir.to_string_lossy().as_bytes())
+        .map_err(|e| VolumeError::IO(marker, e))?;
+
+    for volume in volumes.iter() {
+        fs::create_dir_all(&volume.source)
+            .map_err(|e| VolumeError::IO(volume.source.clone(), e))?;
+    }
+
+    Ok(volumes)
+}
+
+/// Volumes existing for the project, with their location on the host
+pub fn list(project_dir: &Path) -> Result<Vec<(String, PathBuf)>, VolumeError> {
+    let dir = project_volumes_dir(project_dir)?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(VolumeError::IO(dir, e)),
+    };
+
+    let mut out = Vec::new();
+    for entry in entries {
+        let entry = entry.map_err(|e| VolumeError::IO(dir.clone(), e))?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if name != PROJECT_MARKER {
+            out.push((name, entry.path()));
+        }
+    }
+    out.sort();
+
+    Ok(out)
+}
+
+pub fn remove(project_dir: &Path, name: &str) -> Result<(), VolumeError> {
+    check_name(name)?;
+    let path = project_volumes_dir(project_dir)?.join(name);
+    match fs::remove_dir_all(&path) {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Err(VolumeError::NotFound(name.to_string())),
+        Err(e) => Err(VolumeError::IO(path, e)),
+    }
+}
+
+/// Outcome of `prune`
+#[derive(Debug, Default)]
+pub struct Pruned {
+    pub removed: Vec<PathBuf>,
+    /// Volumes of projects which can not be found, with the directory of the project
+    pub missing: Vec<(PathBuf, PathBuf)>,
+}
+
+/// Remove the volumes of the project which are not declared anymore
+///
+/// The volumes of projects which can not be found (deleted, or on a disk which is not mounted)
+/// are only removed when `missing` is set.
+pub fn prune(
+    project_dir: &Path,
+    declared: &BTreeMap<String, String>,
+    missing: bool,
+) -> Result<Pruned, VolumeError> {
+    let mut out = Pruned::default();
+
+    for (name, path) in list(project_dir)? {
+        if !declared.contains_key(&name) {
+            fs::remove_dir_all(&path).map_err(|e| VolumeError::IO(path.clone(), e))?;
+            out.removed.push(path);
+        }
+    }
+
+    let dir = volumes_dir()?;
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(out),
+        Err(e) => return Err(VolumeError::IO(dir, e)),
+    };
+    for entry in entries {
+        let entry = entry.map_err(|e| VolumeError::IO(dir.clone(), e))?;
+        let path = entry.path();
+        let project = match fs::read_to_string(path.join(PROJECT_MARKER)) {
+            Ok(project) => PathBuf::from(project),
+            // Not created by laurn, do not touch
+            Err(_) => continue,
+        };
+        if project.exists() {
+            continue;
+        }
+        if missing {
+            fs::remove_dir_all(&path).map_err(|e| VolumeError::IO(path.clone(), e))?;
+            out.removed.push(path);
+        } else {
+            out.missing.push((project, path));
+        }
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert!(check_name("cargo").is_ok());
+        assert!(check_name("").is_err());
+        assert!(check_name(".project").is_err());
+        assert!(check_name("../cargo").is_err());
+    }
+
+    #[test]
+    fn targets() {
+        let project = Path::new("/home/user/project");
+        assert_eq!(
+            target(project, "modules", "./node_modules").unwrap(),
+            PathBuf::from("/home/user/project/node_modules")
+        );
+        assert!(target(project, "cargo", "~/.cargo/registry")
+            .unwrap()
+            .ends_with(".cargo/registry"));
+        for invalid in [
+            "/opt/cache",
+            "../cache",
+            "cache/../../x",
+            ".",
+            "",
+            "~/",
+            "~/..",
+        ]
+        .iter()
+        {
+            match target(project, "cache", invalid) {
+                Err(VolumeError::InvalidTarget(_, ref target)) => assert_eq!(target, invalid),
+                other => panic!("{:?} was accepted: {:?}", invalid, other),
+            }
+        }
+    }
+}